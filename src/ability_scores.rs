@@ -0,0 +1,462 @@
+// Rolls a D&D style array of six ability scores in the tray, then lets them be
+// dragged onto the abilities they should go to.
+//
+// When a method's dice for all six scores don't fit in the tray at once, the
+// scores are rolled in batches, each one once the previous batch has settled.
+
+use gtk::{gdk, gio, glib, prelude::*};
+use adw::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::dice_area::{MAX_DICE, SPIN_DURATION};
+use crate::notation::{self, Group, Roll};
+use crate::roll_history::RollSnapshot;
+use crate::window::DiceWindow;
+
+const ABILITIES: [&str; 6] = ["STR", "DEX", "CON", "INT", "WIS", "CHA"];
+
+const STANDARD_ARRAY: [i32; 6] = [15, 14, 13, 12, 10, 8];
+
+// Cost of each score from 8 to 15 under the 27 point buy
+const POINT_BUY_COSTS: [i32; 8] = [0, 1, 2, 3, 4, 5, 7, 9];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Method {
+    FourDropLowest,
+    ThreeInOrder,
+    TwoPlusSix,
+    StandardArray,
+}
+
+impl Method {
+    const ALL: [Method; 4] = [Method::FourDropLowest, Method::ThreeInOrder, Method::TwoPlusSix, Method::StandardArray];
+
+    fn label(self) -> &'static str {
+        match self {
+            Method::FourDropLowest => "4d6, Drop Lowest",
+            Method::ThreeInOrder => "3d6 in Order",
+            Method::TwoPlusSix => "2d6 + 6",
+            Method::StandardArray => "Standard Array",
+        }
+    }
+
+    // What each score is rolled with; the standard array isn't rolled at all
+    fn notation(self) -> Option<&'static str> {
+        match self {
+            Method::FourDropLowest => Some("4d6dl1"),
+            Method::ThreeInOrder => Some("3d6"),
+            Method::TwoPlusSix => Some("2d6+6"),
+            Method::StandardArray => None,
+        }
+    }
+
+    // Scores go to the abilities in the order they were rolled
+    fn in_order(self) -> bool {
+        self == Method::ThreeInOrder
+    }
+}
+
+fn ability_modifier(score: i32) -> i32 {
+    (score - 10).div_euclid(2)
+}
+
+// Points the scores would cost under point buy, if every one of them is on its table
+fn point_buy(scores: &[i32]) -> Option<i32> {
+    scores.iter()
+        .map(|&score| POINT_BUY_COSTS.get(usize::try_from(score - 8).ok()?).copied())
+        .sum()
+}
+
+#[derive(Default)]
+struct State {
+    method: Option<Method>,
+    scores: Vec<i32>,
+    // Set once the dice that rolled `scores` have settled
+    revealed: bool,
+    // Counts rolls, so an earlier roll settling doesn't reveal a later one
+    roll_id: u32,
+    // Index into `scores` given to each ability
+    assigned: [Option<usize>; 6],
+    // The tray as its last batch was rolled, recorded with the array when it is
+    // saved; earlier batches go to Recents as the next one rolls
+    snapshot: Option<RollSnapshot>,
+}
+
+impl State {
+    fn assign(&mut self, ability: usize, score: usize) {
+        // Whichever ability had the score swaps to this one's
+        if let Some(other) = self.assigned.iter().position(|&a| a == Some(score)) {
+            self.assigned[other] = self.assigned[ability];
+        }
+        self.assigned[ability] = Some(score);
+    }
+
+    fn is_complete(&self) -> bool {
+        self.revealed && self.assigned.iter().all(Option::is_some)
+    }
+
+    fn abilities(&self) -> Vec<(String, i32)> {
+        ABILITIES.iter()
+            .zip(self.assigned)
+            .filter_map(|(ability, score)| Some((ability.to_string(), self.scores[score?])))
+            .collect()
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = format!("Ability Scores ({})\n", self.method.map_or("", Method::label));
+        for (ability, score) in self.abilities() {
+            text.push_str(&format!("{} {} ({:+})\n", ability, score, ability_modifier(score)));
+        }
+        if let Some(points) = point_buy(&self.scores) {
+            text.push_str(&format!("Point buy: {}\n", points));
+        }
+        text
+    }
+}
+
+struct Ui {
+    scores_box: gtk::Box,
+    ability_rows: Vec<adw::ActionRow>,
+    ability_labels: Vec<gtk::Label>,
+    point_buy_label: gtk::Label,
+    save_button: gtk::Button,
+    export_button: gtk::Button,
+}
+
+pub fn show(window: &DiceWindow) {
+    let state = Rc::new(RefCell::new(State::default()));
+
+    let labels: Vec<&str> = Method::ALL.iter().map(|method| method.label()).collect();
+    let method_row = adw::ComboRow::builder()
+        .title("Method")
+        .model(&gtk::StringList::new(&labels))
+        .build();
+    let method_list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(vec!["boxed-list"])
+        .build();
+    method_list.append(&method_row);
+
+    let roll_button = gtk::Button::builder()
+        .label("Roll")
+        .halign(gtk::Align::Center)
+        .css_classes(vec!["pill", "suggested-action"])
+        .build();
+
+    let scores_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .halign(gtk::Align::Center)
+        .spacing(8)
+        .build();
+
+    let scores_hint = gtk::Label::builder()
+        .label("Drag each score onto an ability")
+        .css_classes(vec!["dim-label", "caption"])
+        .build();
+
+    let abilities_list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(vec!["boxed-list"])
+        .build();
+    let mut ability_rows = Vec::new();
+    let mut ability_labels = Vec::new();
+    for ability in ABILITIES {
+        let label = gtk::Label::new(Some("—"));
+        let row = adw::ActionRow::builder().title(ability).build();
+        row.add_suffix(&label);
+        abilities_list.append(&row);
+        ability_rows.push(row);
+        ability_labels.push(label);
+    }
+
+    let in_order_button = gtk::Button::builder()
+        .label("Assign in Order")
+        .halign(gtk::Align::End)
+        .css_classes(vec!["flat"])
+        .build();
+
+    let point_buy_label = gtk::Label::builder()
+        .xalign(0.0)
+        .wrap(true)
+        .css_classes(vec!["dim-label"])
+        .build();
+
+    let content = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(12)
+        .margin_top(12)
+        .margin_bottom(12)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    content.append(&method_list);
+    content.append(&roll_button);
+    content.append(&scores_box);
+    content.append(&scores_hint);
+    content.append(&abilities_list);
+    content.append(&in_order_button);
+    content.append(&point_buy_label);
+
+    let export_button = gtk::Button::builder()
+        .label("Export…")
+        .sensitive(false)
+        .build();
+    let save_button = gtk::Button::builder()
+        .label("Save to History")
+        .sensitive(false)
+        .css_classes(vec!["suggested-action"])
+        .build();
+    let actions = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .halign(gtk::Align::End)
+        .spacing(8)
+        .margin_top(6)
+        .margin_bottom(6)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    actions.append(&export_button);
+    actions.append(&save_button);
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&content));
+    toolbar.add_bottom_bar(&actions);
+
+    let dialog = adw::Dialog::builder()
+        .title("Ability Scores")
+        .content_width(360)
+        .child(&toolbar)
+        .build();
+
+    let ui = Rc::new(Ui {
+        scores_box,
+        ability_rows,
+        ability_labels,
+        point_buy_label,
+        save_button: save_button.clone(),
+        export_button: export_button.clone(),
+    });
+
+    for (ability, row) in ui.ability_rows.iter().enumerate() {
+        let drop = gtk::DropTarget::new(u32::static_type(), gdk::DragAction::COPY);
+        let state = state.clone();
+        let ui_weak = Rc::downgrade(&ui);
+        drop.connect_drop(move |_, value, _, _| {
+            let (Ok(score), Some(ui)) = (value.get::<u32>(), ui_weak.upgrade()) else { return false };
+            state.borrow_mut().assign(ability, score as usize);
+            refresh(&ui, &state);
+            true
+        });
+        row.add_controller(drop);
+    }
+
+    let window_weak = window.downgrade();
+    let state_clone = state.clone();
+    let ui_clone = ui.clone();
+    roll_button.connect_clicked(move |_| {
+        let Some(window) = window_weak.upgrade() else { return };
+        let method = Method::ALL[method_row.selected() as usize % Method::ALL.len()];
+        roll(&window, method, &ui_clone, &state_clone);
+    });
+
+    let state_clone = state.clone();
+    let ui_clone = ui.clone();
+    in_order_button.connect_clicked(move |_| {
+        let mut s = state_clone.borrow_mut();
+        for (ability, assigned) in s.assigned.iter_mut().enumerate() {
+            *assigned = Some(ability);
+        }
+        drop(s);
+        refresh(&ui_clone, &state_clone);
+    });
+
+    let window_weak = window.downgrade();
+    let state_clone = state.clone();
+    let dialog_weak = dialog.downgrade();
+    save_button.connect_clicked(move |_| {
+        let Some(window) = window_weak.upgrade() else { return };
+        let s = state_clone.borrow();
+        let mut snapshot = s.snapshot.clone().unwrap_or_default();
+        snapshot.abilities = s.abilities();
+        window.record(snapshot);
+        if let Some(dialog) = dialog_weak.upgrade() {
+            dialog.close();
+        }
+    });
+
+    let window_weak = window.downgrade();
+    let state_clone = state.clone();
+    export_button.connect_clicked(move |_| {
+        let Some(window) = window_weak.upgrade() else { return };
+        let text = state_clone.borrow().to_text();
+        let file_dialog = gtk::FileDialog::builder()
+            .title("Export Ability Scores")
+            .initial_name("ability-scores.txt")
+            .build();
+        file_dialog.save(Some(&window), None::<&gio::Cancellable>, move |result| {
+            if let Ok(file) = result {
+                if let Err(err) = file.replace_contents(
+                    text.as_bytes(),
+                    None,
+                    false,
+                    gio::FileCreateFlags::REPLACE_DESTINATION,
+                    None::<&gio::Cancellable>,
+                ) {
+                    println!("Could not export ability scores: {}", err);
+                }
+            }
+        });
+    });
+
+    refresh(&ui, &state);
+    dialog.present(Some(window));
+}
+
+fn roll(window: &DiceWindow, method: Method, ui: &Rc<Ui>, state: &Rc<RefCell<State>>) {
+    let mut s = state.borrow_mut();
+    s.method = Some(method);
+    s.roll_id += 1;
+    s.assigned = [None; 6];
+
+    let Some(roll) = method.notation().and_then(|text| notation::parse(text).ok()) else {
+        s.scores = STANDARD_ARRAY.to_vec();
+        s.revealed = true;
+        s.snapshot = None;
+        drop(s);
+        refresh(ui, state);
+        return;
+    };
+
+    // The scores are known straight away, but stay hidden until the last dice land
+    s.scores = Vec::new();
+    s.revealed = false;
+    s.snapshot = None;
+    let roll_id = s.roll_id;
+    drop(s);
+    roll_batches(window, batches(&roll), method, ui, state, roll_id);
+}
+
+// Each score as a group of its own, split into rolls that fit in the tray
+fn batches(roll: &Roll) -> Vec<Roll> {
+    let per_batch = (MAX_DICE / roll.dice_count().max(1) as usize).clamp(1, ABILITIES.len());
+    let scores: Vec<Group> = (1..=ABILITIES.len())
+        .map(|i| Group { name: Some(format!("Score {}", i)), expr: roll.groups[0].expr.clone() })
+        .collect();
+    scores.chunks(per_batch)
+        .map(|groups| Roll { groups: groups.to_vec() })
+        .collect()
+}
+
+fn roll_batches(window: &DiceWindow, mut batches: Vec<Roll>, method: Method, ui: &Rc<Ui>, state: &Rc<RefCell<State>>, roll_id: u32) {
+    let batch = batches.remove(0);
+    if window.roll_notation(&batch, 1).is_err() {
+        return;
+    }
+    let dice_area = window.dice_area();
+    let mut s = state.borrow_mut();
+    s.scores.extend(dice_area.group_totals());
+    s.snapshot = Some(dice_area.dice_snapshot());
+    drop(s);
+    refresh(ui, state);
+
+    let window_weak = window.downgrade();
+    let ui_weak = Rc::downgrade(ui);
+    let state = state.clone();
+    glib::timeout_add_local_once(Duration::from_secs_f32(SPIN_DURATION), move || {
+        let (Some(window), Some(ui)) = (window_weak.upgrade(), ui_weak.upgrade()) else { return };
+        if state.borrow().roll_id != roll_id {
+            return;
+        }
+        if !batches.is_empty() {
+            roll_batches(&window, batches, method, &ui, &state, roll_id);
+            return;
+        }
+
+        let mut s = state.borrow_mut();
+        s.revealed = true;
+        if method.in_order() {
+            for (ability, assigned) in s.assigned.iter_mut().enumerate() {
+                *assigned = Some(ability);
+            }
+        }
+        drop(s);
+        refresh(&ui, &state);
+    });
+}
+
+fn refresh(ui: &Ui, state: &RefCell<State>) {
+    let s = state.borrow();
+
+    while let Some(child) = ui.scores_box.first_child() {
+        ui.scores_box.remove(&child);
+    }
+    for (i, &score) in s.scores.iter().enumerate() {
+        let text = if s.revealed { score.to_string() } else { "?".to_string() };
+        let chip = gtk::Label::builder()
+            .label(&text)
+            .width_chars(3)
+            .css_classes(vec!["card", "title-3"])
+            .build();
+        if s.assigned.contains(&Some(i)) {
+            chip.add_css_class("dim-label");
+        }
+        if s.revealed {
+            let drag = gtk::DragSource::new();
+            drag.set_actions(gdk::DragAction::COPY);
+            drag.set_content(Some(&gdk::ContentProvider::for_value(&(i as u32).to_value())));
+            let chip_weak = chip.downgrade();
+            drag.connect_drag_begin(move |source, _| {
+                if let Some(chip) = chip_weak.upgrade() {
+                    source.set_icon(Some(&gtk::WidgetPaintable::new(Some(&chip))), 0, 0);
+                }
+            });
+            chip.add_controller(drag);
+        }
+        ui.scores_box.append(&chip);
+    }
+
+    for (ability, label) in ui.ability_labels.iter().enumerate() {
+        let text = match s.assigned[ability] {
+            Some(i) if s.revealed => format!("{} ({:+})", s.scores[i], ability_modifier(s.scores[i])),
+            _ => "—".to_string(),
+        };
+        label.set_text(&text);
+    }
+
+    let point_buy_text = match (s.revealed, point_buy(&s.scores)) {
+        (false, _) => String::new(),
+        (true, Some(points)) => format!("Point-buy equivalent: {} points", points),
+        (true, None) => "No point-buy equivalent: every score must be from 8 to 15".to_string(),
+    };
+    ui.point_buy_label.set_text(&point_buy_text);
+    ui.point_buy_label.set_visible(!point_buy_text.is_empty());
+
+    ui.save_button.set_sensitive(s.is_complete());
+    ui.export_button.set_sensitive(s.is_complete());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probability::RollDefinition;
+
+    #[test]
+    fn every_score_is_rolled_in_the_tray() {
+        for method in Method::ALL {
+            let Some(roll) = method.notation().and_then(|text| notation::parse(text).ok()) else { continue };
+            let batches = batches(&roll);
+            assert!(batches.iter().all(|batch| batch.dice_count() as usize <= MAX_DICE), "{} overflows the tray", method.label());
+
+            let scores: Vec<&Group> = batches.iter().flat_map(|batch| &batch.groups).collect();
+            assert_eq!(scores.len(), ABILITIES.len());
+            for score in scores {
+                let single = Roll { groups: vec![Group { name: None, expr: score.expr.clone() }] };
+                let dist = RollDefinition::from_roll(&single).distribution().unwrap();
+                assert!(dist.min() >= 3 && dist.max() <= 18, "{} scores {}..={}", method.label(), dist.min(), dist.max());
+            }
+        }
+    }
+}
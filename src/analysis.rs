@@ -0,0 +1,406 @@
+// Shows the odds of a notation roll, or of the dice in the tray: the spread of
+// totals as a histogram alongside its range, mean and deviation. They are worked
+// out exactly where possible, and can be estimated by simulation otherwise.
+
+use gtk::{gdk, glib, prelude::*};
+use adw::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::notation;
+use crate::probability::{Distribution, RollDefinition};
+use crate::simulation::{self, Estimate, Simulation};
+use crate::window::DiceWindow;
+
+const TRAY_HINT: &str = "Leave the notation empty to use the dice in the tray";
+
+// Totals at or above the target are drawn in the accent color
+const TARGET_COLOR: &str = "#3584E4";
+
+#[derive(Default)]
+struct State {
+    def: Option<RollDefinition>,
+    dist: Option<Distribution>,
+    // Rolls behind `dist` when it was simulated rather than worked out exactly
+    trials: Option<u64>,
+    // Set while a simulation is running; dropping it cancels the simulation
+    run: Option<simulation::Handle>,
+    // Until a target is picked, it starts from the first mean worked out
+    target_set: bool,
+}
+
+struct Ui {
+    histogram: gtk::DrawingArea,
+    min_label: gtk::Label,
+    max_label: gtk::Label,
+    hint: gtk::Label,
+    range_label: gtk::Label,
+    mean_label: gtk::Label,
+    std_dev_label: gtk::Label,
+    chance_row: adw::ActionRow,
+    chance_label: gtk::Label,
+    target_row: adw::SpinRow,
+    simulate_button: gtk::Button,
+    cancel_button: gtk::Button,
+    progress_bar: gtk::ProgressBar,
+}
+
+pub fn show(window: &DiceWindow) {
+    let dice_area = window.dice_area();
+    let snapshot = dice_area.dice_snapshot();
+
+    let notation_row = adw::EntryRow::builder()
+        .title("Notation")
+        .text(snapshot.expression.as_deref().unwrap_or_default())
+        .build();
+    let target_row = adw::SpinRow::with_range(-9999.0, 9999.0, 1.0);
+    target_row.set_title("Target");
+
+    let inputs = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(vec!["boxed-list"])
+        .build();
+    inputs.append(&notation_row);
+    inputs.append(&target_row);
+
+    let hint = gtk::Label::builder()
+        .label(TRAY_HINT)
+        .wrap(true)
+        .xalign(0.0)
+        .css_classes(vec!["caption", "dim-label"])
+        .build();
+
+    let histogram = gtk::DrawingArea::builder()
+        .content_height(160)
+        .css_classes(vec!["card"])
+        .build();
+    let min_label = gtk::Label::builder()
+        .hexpand(true)
+        .xalign(0.0)
+        .css_classes(vec!["caption", "dim-label"])
+        .build();
+    let max_label = gtk::Label::builder()
+        .xalign(1.0)
+        .css_classes(vec!["caption", "dim-label"])
+        .build();
+    let axis = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .build();
+    axis.append(&min_label);
+    axis.append(&max_label);
+
+    let stats = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(vec!["boxed-list"])
+        .build();
+    let stat_row = |title: &str| {
+        let label = gtk::Label::new(Some("—"));
+        let row = adw::ActionRow::builder().title(title).build();
+        row.add_suffix(&label);
+        stats.append(&row);
+        (row, label)
+    };
+    let (_, range_label) = stat_row("Range");
+    let (_, mean_label) = stat_row("Mean");
+    let (_, std_dev_label) = stat_row("Standard Deviation");
+    let (chance_row, chance_label) = stat_row("Chance");
+
+    let simulate_button = gtk::Button::builder()
+        .label(format!("Simulate {} Rolls", format_count(simulation::TRIALS)))
+        .hexpand(true)
+        .halign(gtk::Align::Center)
+        .css_classes(vec!["pill"])
+        .build();
+    let progress_bar = gtk::ProgressBar::builder()
+        .hexpand(true)
+        .valign(gtk::Align::Center)
+        .show_text(true)
+        .visible(false)
+        .build();
+    let cancel_button = gtk::Button::builder()
+        .icon_name("process-stop-symbolic")
+        .tooltip_text("Stop Simulating")
+        .css_classes(vec!["flat", "circular"])
+        .visible(false)
+        .build();
+    let simulate_box = gtk::Box::builder()
+        .orientation(gtk::Orientation::Horizontal)
+        .spacing(8)
+        .build();
+    simulate_box.append(&simulate_button);
+    simulate_box.append(&progress_bar);
+    simulate_box.append(&cancel_button);
+
+    let content = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(12)
+        .margin_top(12)
+        .margin_bottom(12)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    content.append(&inputs);
+    content.append(&hint);
+    content.append(&histogram);
+    content.append(&axis);
+    content.append(&stats);
+    content.append(&simulate_box);
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&content));
+
+    let dialog = adw::Dialog::builder()
+        .title("Odds")
+        .content_width(400)
+        .child(&toolbar)
+        .build();
+
+    let ui = Rc::new(Ui {
+        histogram,
+        min_label,
+        max_label,
+        hint,
+        range_label,
+        mean_label,
+        std_dev_label,
+        chance_row,
+        chance_label,
+        target_row,
+        simulate_button,
+        cancel_button,
+        progress_bar,
+    });
+    let state = Rc::new(RefCell::new(State {
+        target_set: snapshot.target.is_some(),
+        ..Default::default()
+    }));
+
+    let state_clone = state.clone();
+    let ui_weak = Rc::downgrade(&ui);
+    ui.histogram.set_draw_func(move |area, cr, width, height| {
+        let s = state_clone.borrow();
+        let (Some(dist), Some(ui)) = (s.dist.as_ref(), ui_weak.upgrade()) else { return };
+        let target = ui.target_row.value() as i32;
+        let highest = dist.odds().values().copied().fold(0.0, f64::max);
+        if highest <= 0.0 {
+            return;
+        }
+
+        let foreground = area.color();
+        let accent = gdk::RGBA::parse(TARGET_COLOR).unwrap_or(foreground);
+        let totals = (dist.max() - dist.min() + 1) as f64;
+        let bar_width = width as f64 / totals;
+        let gap = if bar_width > 4.0 { 1.0 } else { 0.0 };
+        for (&total, &p) in dist.odds() {
+            let color = if total >= target { accent } else { foreground.with_alpha(0.3) };
+            cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, color.alpha() as f64);
+            let bar_height = p / highest * (height as f64 - 8.0);
+            let x = (total - dist.min()) as f64 * bar_width;
+            cr.rectangle(x + gap, height as f64 - bar_height, bar_width - 2.0 * gap, bar_height);
+            cr.fill().ok();
+        }
+    });
+
+    if let Some(target) = snapshot.target {
+        ui.target_row.set_value(target.value as f64);
+    }
+
+    let dice_area_weak = dice_area.downgrade();
+    let state_clone = state.clone();
+    let ui_clone = ui.clone();
+    let update = move |text: &str| {
+        let Some(dice_area) = dice_area_weak.upgrade() else { return };
+        let def = match text.trim() {
+            "" => Some(dice_area.roll_definition())
+                .filter(|def| !def.is_empty())
+                .ok_or_else(|| "There are no dice in the tray".to_string()),
+            text => notation::parse(text)
+                .map(|roll| RollDefinition::from_roll(&roll))
+                .map_err(|err| err.to_string()),
+        };
+
+        let mut s = state_clone.borrow_mut();
+        s.run = None;
+        s.trials = None;
+        s.dist = None;
+        match def.as_ref().map(RollDefinition::distribution) {
+            Ok(Ok(dist)) => {
+                ui_clone.hint.set_label(TRAY_HINT);
+                ui_clone.hint.remove_css_class("error");
+                s.dist = Some(dist);
+            }
+            Ok(Err(message)) => {
+                ui_clone.hint.set_label(&format!("{}. Simulate the roll to estimate its odds.", message));
+                ui_clone.hint.remove_css_class("error");
+            }
+            Err(message) => {
+                ui_clone.hint.set_label(message);
+                ui_clone.hint.add_css_class("error");
+            }
+        }
+        s.def = def.ok();
+        let mean = s.dist.as_ref().filter(|_| !s.target_set).map(Distribution::mean);
+        drop(s);
+
+        if let Some(mean) = mean {
+            state_clone.borrow_mut().target_set = true;
+            ui_clone.target_row.set_value(mean.round());
+        }
+        refresh(&ui_clone, &state_clone);
+    };
+    let update = Rc::new(update);
+
+    let update_clone = update.clone();
+    notation_row.connect_changed(move |row| update_clone(&row.text()));
+
+    let state_clone = state.clone();
+    let ui_weak = Rc::downgrade(&ui);
+    ui.target_row.connect_value_notify(move |_| {
+        if let Some(ui) = ui_weak.upgrade() {
+            refresh(&ui, &state_clone);
+        }
+    });
+
+    let state_clone = state.clone();
+    let ui_weak = Rc::downgrade(&ui);
+    ui.simulate_button.connect_clicked(move |_| {
+        if let Some(ui) = ui_weak.upgrade() {
+            simulate(&ui, &state_clone);
+        }
+    });
+
+    let state_clone = state.clone();
+    let ui_weak = Rc::downgrade(&ui);
+    ui.cancel_button.connect_clicked(move |_| {
+        state_clone.borrow_mut().run = None;
+        if let Some(ui) = ui_weak.upgrade() {
+            refresh(&ui, &state_clone);
+        }
+    });
+
+    let state_clone = state.clone();
+    dialog.connect_closed(move |_| {
+        state_clone.borrow_mut().run = None;
+    });
+
+    update(&notation_row.text());
+    dialog.present(Some(window));
+}
+
+fn simulate(ui: &Rc<Ui>, state: &Rc<RefCell<State>>) {
+    let Some(sim) = state.borrow().def.as_ref().map(Simulation::new) else { return };
+
+    let show = |ui: &Ui, state: &RefCell<State>, estimate: &Estimate| {
+        let mut s = state.borrow_mut();
+        s.dist = Some(estimate.distribution());
+        s.trials = Some(estimate.trials);
+        drop(s);
+        ui.progress_bar.set_fraction(estimate.trials as f64 / simulation::TRIALS as f64);
+        ui.progress_bar.set_text(Some(&format!("{} rolls", format_count(estimate.trials))));
+        refresh(ui, state);
+    };
+
+    let (ui_weak, state_weak) = (Rc::downgrade(ui), Rc::downgrade(state));
+    let on_update = move |estimate: &Estimate| {
+        if let (Some(ui), Some(state)) = (ui_weak.upgrade(), state_weak.upgrade()) {
+            show(&ui, &state, estimate);
+        }
+    };
+    let (ui_weak, state_weak) = (Rc::downgrade(ui), Rc::downgrade(state));
+    let on_done = move |estimate: Estimate| {
+        if let (Some(ui), Some(state)) = (ui_weak.upgrade(), state_weak.upgrade()) {
+            state.borrow_mut().run = None;
+            show(&ui, &state, &estimate);
+        }
+    };
+
+    ui.progress_bar.set_fraction(0.0);
+    ui.progress_bar.set_text(Some("0 rolls"));
+    state.borrow_mut().run = Some(simulation::start(sim, simulation::TRIALS, on_update, on_done));
+    refresh(ui, state);
+}
+
+fn refresh(ui: &Ui, state: &RefCell<State>) {
+    let s = state.borrow();
+    let target = ui.target_row.value() as i32;
+    ui.chance_row.set_title(&glib::markup_escape_text(&format!("Chance of ≥ {}", target)));
+
+    match &s.dist {
+        Some(dist) => {
+            ui.min_label.set_text(&dist.min().to_string());
+            ui.max_label.set_text(&dist.max().to_string());
+            ui.range_label.set_text(&format!("{} – {}", dist.min(), dist.max()));
+            ui.std_dev_label.set_text(&format!("{:.2}", dist.std_dev()));
+            let chance = dist.at_least(target);
+            match s.trials {
+                // Estimates carry their 95% confidence interval
+                Some(trials) => {
+                    ui.mean_label.set_text(&format!("{:.2} ± {:.2}", dist.mean(), simulation::mean_margin(dist, trials)));
+                    let (low, high) = simulation::chance_interval(chance, trials);
+                    ui.chance_label.set_text(&format!(
+                        "{} ({} – {})",
+                        format_percent(chance),
+                        format_percent(low),
+                        format_percent(high),
+                    ));
+                }
+                None => {
+                    ui.mean_label.set_text(&format!("{:.2}", dist.mean()));
+                    ui.chance_label.set_text(&format_percent(chance));
+                }
+            }
+        }
+        None => {
+            for label in [&ui.min_label, &ui.max_label] {
+                label.set_text("");
+            }
+            for label in [&ui.range_label, &ui.mean_label, &ui.std_dev_label, &ui.chance_label] {
+                label.set_text("—");
+            }
+        }
+    }
+
+    let running = s.run.is_some();
+    ui.simulate_button.set_visible(!running);
+    ui.simulate_button.set_sensitive(s.def.is_some());
+    ui.progress_bar.set_visible(running || s.trials.is_some());
+    ui.cancel_button.set_visible(running);
+    ui.histogram.queue_draw();
+}
+
+// Rounded to a tenth of a percent, without calling a possible total impossible
+fn format_percent(p: f64) -> String {
+    match p * 100.0 {
+        percent if percent > 0.0 && percent < 0.05 => "< 0.1%".to_string(),
+        percent if percent < 100.0 && percent > 99.95 => "> 99.9%".to_string(),
+        percent => format!("{:.1}%", percent),
+    }
+}
+
+// Groups the digits in threes, e.g. "1,000,000"
+pub fn format_count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut text = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            text.push(',');
+        }
+        text.push(digit);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_are_grouped_in_threes() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1000), "1,000");
+        assert_eq!(format_count(123_456), "123,456");
+        assert_eq!(format_count(1_234_567), "1,234,567");
+    }
+}
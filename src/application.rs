@@ -51,9 +51,17 @@ mod imp {
             obj.set_accels_for_action("win.roll-d10", &["0"]);
             obj.set_accels_for_action("win.roll-d12", &["<primary>2"]);
             obj.set_accels_for_action("win.roll-d20", &["<primary>0"]);
+            obj.set_accels_for_action("win.roll-d100", &["p"]);
+            obj.set_accels_for_action("win.roll-df", &["f"]);
             obj.set_accels_for_action("win.reroll", &["r"]);
             obj.set_accels_for_action("win.clear", &["c"]);
+            obj.set_accels_for_action("win.select-dice", &["s"]);
+            obj.set_accels_for_action("win.modifier-increase", &["plus", "equal", "KP_Add"]);
+            obj.set_accels_for_action("win.modifier-decrease", &["minus", "KP_Subtract"]);
             obj.set_accels_for_action("win.toggle-sidebar", &["F9"]);
+            obj.set_accels_for_action("win.focus-notation", &["<primary>l"]);
+            obj.set_accels_for_action("win.d20-mode::advantage", &["<alt>a"]);
+            obj.set_accels_for_action("win.d20-mode::disadvantage", &["<alt>d"]);
         }
     }
 
@@ -75,6 +83,11 @@ mod imp {
             // Ask the window manager/compositor to present the window
             window.present();
         }
+
+        fn shutdown(&self) {
+            crate::statistics::flush();
+            self.parent_shutdown();
+        }
     }
 
     impl GtkApplicationImpl for DiceApplication {}
@@ -0,0 +1,191 @@
+// User-defined dice whose faces carry their own labels, values and symbols.
+// Definitions live in custom-dice.json next to favorites.json, e.g.
+//
+//   [{ "name": "Ability", "mesh": "Eight", "faces": [
+//       { "label": "" },
+//       { "label": "S", "symbols": ["Success"] },
+//       { "label": "SA", "symbols": ["Success", "Advantage"] }, ... ] }]
+//
+// Face N of a definition sits on face N of the mesh it is drawn with.
+
+use gtk::glib;
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::die::DieKind;
+
+pub const MESHES: [DieKind; 6] = [
+    DieKind::Four,
+    DieKind::Six,
+    DieKind::Eight,
+    DieKind::Ten,
+    DieKind::Twelve,
+    DieKind::Twenty,
+];
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Face {
+    pub label: String,
+    #[serde(default)]
+    pub value: i32,
+    #[serde(default)]
+    pub symbols: Vec<String>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct CustomDie {
+    pub name: String,
+    pub mesh: DieKind,
+    pub faces: Vec<Face>,
+}
+
+impl CustomDie {
+    // Derived from the name so history entries keep pointing at the same die
+    pub fn id(&self) -> u32 {
+        self.name.bytes().fold(0x811c_9dc5u32, |hash, b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+    }
+
+    pub fn is_valid(&self) -> bool {
+        !self.name.trim().is_empty()
+            && MESHES.contains(&self.mesh)
+            && self.faces.len() == self.mesh.sides() as usize
+    }
+
+    pub fn face(&self, face: u32) -> Option<&Face> {
+        self.faces.get((face as usize).checked_sub(1)?)
+    }
+
+    // Faces only carry symbols, so a numeric total would mean nothing
+    pub fn is_symbolic(&self) -> bool {
+        self.faces.iter().all(|face| face.value == 0)
+    }
+}
+
+thread_local! {
+    static DEFINITIONS: RefCell<Vec<CustomDie>> = RefCell::new(load());
+}
+
+pub fn definitions() -> Vec<CustomDie> {
+    DEFINITIONS.with(|defs| defs.borrow().clone())
+}
+
+// Runs `f` on the definition with the given id, if it still exists
+pub fn with<R>(id: u32, f: impl FnOnce(&CustomDie) -> R) -> Option<R> {
+    DEFINITIONS.with(|defs| defs.borrow().iter().find(|def| def.id() == id).map(f))
+}
+
+// Adds a definition, replacing any with the same name
+pub fn add(def: CustomDie) {
+    DEFINITIONS.with(|defs| {
+        let mut defs = defs.borrow_mut();
+        let id = def.id();
+        match defs.iter_mut().find(|d| d.id() == id) {
+            Some(existing) => *existing = def,
+            None => defs.push(def),
+        }
+        save(&defs);
+    });
+}
+
+pub fn remove(id: u32) {
+    DEFINITIONS.with(|defs| {
+        let mut defs = defs.borrow_mut();
+        defs.retain(|d| d.id() != id);
+        save(&defs);
+    });
+}
+
+pub fn symbols(kind: DieKind, face: u32) -> Vec<String> {
+    match kind {
+        DieKind::Defined(id) => with(id, |def| def.face(face).map(|f| f.symbols.clone()))
+            .flatten()
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    }
+}
+
+pub fn is_symbolic(kind: DieKind) -> bool {
+    match kind {
+        DieKind::Defined(id) => with(id, CustomDie::is_symbolic).unwrap_or(false),
+        _ => false,
+    }
+}
+
+// Counts symbols in the order they first appear, e.g. "2 Success, 1 Threat"
+pub fn format_tally<'a>(symbols: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let mut counts: Vec<(&str, u32)> = Vec::new();
+    for symbol in symbols {
+        match counts.iter_mut().find(|(s, _)| *s == symbol) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((symbol, 1)),
+        }
+    }
+    let parts: Vec<String> = counts.iter().map(|(s, n)| format!("{} {}", n, s)).collect();
+    (!parts.is_empty()).then(|| parts.join(", "))
+}
+
+// A roll total alongside its symbol tally; symbolic rolls show only the tally
+pub fn format_total(total: i32, tally: Option<String>, symbolic: bool) -> String {
+    match tally {
+        Some(tally) if symbolic => tally,
+        Some(tally) => format!("{} · {}", total, tally),
+        None => total.to_string(),
+    }
+}
+
+// Reads faces written one per line as "label = value : Symbol, Symbol",
+// where both the value and the symbols are optional
+pub fn parse_faces(text: &str) -> Result<Vec<Face>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let (face, symbols) = line.split_once(':').unwrap_or((line, ""));
+            let (label, value) = face.split_once('=').unwrap_or((face, ""));
+            let value = match value.trim() {
+                "" => 0,
+                value => value.parse().map_err(|_| format!("Line {}: \"{}\" is not a number", i + 1, value))?,
+            };
+            let symbols = symbols.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect();
+            Ok(Face { label: label.trim().to_string(), value, symbols })
+        })
+        .collect()
+}
+
+fn path() -> PathBuf {
+    let mut path = glib::user_data_dir();
+    path.push("dice");
+    path.push("custom-dice.json");
+    path
+}
+
+fn save(defs: &[CustomDie]) {
+    let path = path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    if let Ok(json) = serde_json::to_string_pretty(defs) {
+        fs::write(&path, json).ok();
+    }
+}
+
+fn load() -> Vec<CustomDie> {
+    let defs: Vec<CustomDie> = fs::read_to_string(path())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    defs.into_iter()
+        .filter(|def| {
+            let valid = def.is_valid();
+            if !valid {
+                println!("Skipping custom die \"{}\": it needs one face per side of its mesh", def.name);
+            }
+            valid
+        })
+        .collect()
+}
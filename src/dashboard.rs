@@ -0,0 +1,254 @@
+// Statistics on every die rolled: how often each face came up over the die's
+// lifetime next to what a fair die would give, and how lucky each session was.
+
+use gtk::{gio, glib, prelude::*};
+use adw::prelude::*;
+
+use crate::statistics::{self, Statistics};
+use crate::window::DiceWindow;
+
+// Sessions at least this many deviations from average count as lucky or unlucky
+const LUCK_THRESHOLD: f64 = 1.0;
+
+pub fn show(window: &DiceWindow) {
+    let stack = adw::ViewStack::new();
+    populate(&stack);
+
+    let switcher = adw::ViewSwitcher::builder()
+        .stack(&stack)
+        .policy(adw::ViewSwitcherPolicy::Wide)
+        .build();
+    let header = adw::HeaderBar::builder()
+        .title_widget(&switcher)
+        .build();
+
+    let reset_button = gtk::Button::builder()
+        .icon_name("user-trash-symbolic")
+        .tooltip_text("Reset Statistics")
+        .build();
+    let export_button = gtk::Button::builder()
+        .icon_name("document-save-symbolic")
+        .tooltip_text("Export…")
+        .build();
+    header.pack_start(&reset_button);
+    header.pack_end(&export_button);
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&header);
+    toolbar.set_content(Some(&stack));
+
+    let dialog = adw::Dialog::builder()
+        .title("Statistics")
+        .content_width(480)
+        .content_height(600)
+        .child(&toolbar)
+        .build();
+
+    let dialog_weak = dialog.downgrade();
+    let stack_weak = stack.downgrade();
+    reset_button.connect_clicked(move |_| {
+        let Some(dialog) = dialog_weak.upgrade() else { return };
+        let confirm = adw::AlertDialog::builder()
+            .heading("Reset Statistics?")
+            .body("Every count, streak and session so far will be forgotten")
+            .build();
+        confirm.add_responses(&[("cancel", "Cancel"), ("reset", "Reset")]);
+        confirm.set_response_appearance("reset", adw::ResponseAppearance::Destructive);
+        confirm.set_default_response(Some("cancel"));
+        confirm.set_close_response("cancel");
+        let stack_weak = stack_weak.clone();
+        confirm.connect_response(None, move |_, response| {
+            if response == "reset" {
+                statistics::reset();
+                if let Some(stack) = stack_weak.upgrade() {
+                    populate(&stack);
+                }
+            }
+        });
+        confirm.present(Some(&dialog));
+    });
+
+    let window_weak = window.downgrade();
+    export_button.connect_clicked(move |_| {
+        let Some(window) = window_weak.upgrade() else { return };
+        let csv = statistics::to_csv(&statistics::statistics());
+        let file_dialog = gtk::FileDialog::builder()
+            .title("Export Statistics")
+            .initial_name("dice-statistics.csv")
+            .build();
+        file_dialog.save(Some(&window), None::<&gio::Cancellable>, move |result| {
+            if let Ok(file) = result {
+                if let Err(err) = file.replace_contents(
+                    csv.as_bytes(),
+                    None,
+                    false,
+                    gio::FileCreateFlags::REPLACE_DESTINATION,
+                    None::<&gio::Cancellable>,
+                ) {
+                    println!("Could not export statistics: {}", err);
+                }
+            }
+        });
+    });
+
+    dialog.present(Some(window));
+}
+
+// Fills the stack with a page for the dice and one for the sessions, replacing any already there
+fn populate(stack: &adw::ViewStack) {
+    while let Some(child) = stack.first_child() {
+        stack.remove(&child);
+    }
+
+    let stats = statistics::statistics();
+    let dice_page = stack.add_titled(&build_dice_page(&stats), Some("dice"), "Dice");
+    dice_page.set_icon_name(Some("view-grid-symbolic"));
+    let sessions_page = stack.add_titled(&build_sessions_page(&stats), Some("sessions"), "Sessions");
+    sessions_page.set_icon_name(Some("document-open-recent-symbolic"));
+}
+
+fn empty_page() -> gtk::Widget {
+    adw::StatusPage::builder()
+        .icon_name("org.lesslie.dice-symbolic")
+        .title("No Rolls Yet")
+        .description("Dice are counted here as they land")
+        .build()
+        .upcast()
+}
+
+fn build_dice_page(stats: &Statistics) -> gtk::Widget {
+    if stats.tallies.is_empty() {
+        return empty_page();
+    }
+
+    let page = adw::PreferencesPage::new();
+    for tally in &stats.tallies {
+        let group = adw::PreferencesGroup::builder()
+            .title(glib::markup_escape_text(&tally.kind.name()).as_str())
+            .description(format!("{} rolls", tally.rolls()))
+            .build();
+
+        group.add(&face_histogram(tally.counts.clone()));
+
+        let list = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(vec!["boxed-list"])
+            .margin_top(12)
+            .build();
+        let stat_row = |title: &str, text: String| {
+            let row = adw::ActionRow::builder().title(title).build();
+            row.add_suffix(&gtk::Label::new(Some(&text)));
+            list.append(&row);
+        };
+        stat_row("Average", format!("{:.2} (expected {:.2})", tally.average(), tally.expected()));
+        stat_row("Longest Streaks", format!("{} above · {} below average", tally.longest_high, tally.longest_low));
+        stat_row("Current Streak", match tally.streak {
+            0 => "—".to_string(),
+            streak if streak > 0 => format!("{} above average", streak),
+            streak => format!("{} below average", -streak),
+        });
+        group.add(&list);
+
+        page.add(&group);
+    }
+    page.upcast()
+}
+
+// How often each face came up, with a line where a fair die would put every bar
+pub fn face_histogram(counts: Vec<u64>) -> gtk::DrawingArea {
+    let histogram = gtk::DrawingArea::builder()
+        .content_height(120)
+        .css_classes(vec!["card"])
+        .tooltip_text(format!("Faces 1 to {}", counts.len()))
+        .build();
+    histogram.set_draw_func(move |area, cr, width, height| {
+        let rolls: u64 = counts.iter().sum();
+        let expected = rolls as f64 / counts.len().max(1) as f64;
+        let highest = counts.iter().copied().max().unwrap_or(0) as f64;
+        let scale = highest.max(expected);
+        if scale <= 0.0 {
+            return;
+        }
+
+        let color = area.color();
+        let usable = height as f64 - 8.0;
+        let bar_width = width as f64 / counts.len() as f64;
+        let gap = if bar_width > 4.0 { 1.0 } else { 0.0 };
+        cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, 0.3);
+        for (i, &count) in counts.iter().enumerate() {
+            let bar_height = count as f64 / scale * usable;
+            cr.rectangle(i as f64 * bar_width + gap, height as f64 - bar_height, bar_width - 2.0 * gap, bar_height);
+        }
+        cr.fill().ok();
+
+        let y = height as f64 - expected / scale * usable;
+        cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, 0.8);
+        cr.set_line_width(1.0);
+        cr.set_dash(&[4.0, 4.0], 0.0);
+        cr.move_to(0.0, y);
+        cr.line_to(width as f64, y);
+        cr.stroke().ok();
+    });
+    histogram
+}
+
+fn build_sessions_page(stats: &Statistics) -> gtk::Widget {
+    if stats.sessions.is_empty() {
+        return empty_page();
+    }
+
+    let page = adw::PreferencesPage::new();
+    let group = adw::PreferencesGroup::builder()
+        .title("Sessions")
+        .description("Luck is how far a session's dice came out from average, in standard deviations")
+        .build();
+    for session in stats.sessions.iter().rev() {
+        let title = if statistics::is_current(session) {
+            "This Session".to_string()
+        } else {
+            glib::DateTime::from_unix_local(session.started)
+                .and_then(|date| date.format("%x %R"))
+                .map(String::from)
+                .unwrap_or_default()
+        };
+        let row = adw::ExpanderRow::builder()
+            .title(title)
+            .subtitle(format!("{} dice", session.rolls()))
+            .build();
+
+        let luck = session.luck();
+        let luck_label = gtk::Label::new(Some(&format_luck(luck)));
+        if luck >= LUCK_THRESHOLD {
+            luck_label.add_css_class("success");
+        } else if luck <= -LUCK_THRESHOLD {
+            luck_label.add_css_class("error");
+        }
+        row.add_suffix(&luck_label);
+
+        for tally in &session.tallies {
+            row.add_row(&adw::ActionRow::builder()
+                .title(glib::markup_escape_text(&tally.kind.name()).as_str())
+                .subtitle(format!(
+                    "{} rolls · average {:.2}, expected {:.2}",
+                    tally.rolls(),
+                    tally.average(),
+                    tally.expected(),
+                ))
+                .build());
+        }
+        group.add(&row);
+    }
+    page.add(&group);
+    page.upcast()
+}
+
+fn format_luck(luck: f64) -> String {
+    let word = if luck >= LUCK_THRESHOLD {
+        "Lucky"
+    } else if luck <= -LUCK_THRESHOLD {
+        "Unlucky"
+    } else {
+        "Average"
+    };
+    format!("{} ({:+.1}σ)", word, luck)
+}
@@ -1,6 +1,11 @@
 use gtk::{gdk, glib, prelude::*, subclass::prelude::*};
 
-use crate::die::{Die, DieKind};
+use crate::custom_dice;
+use crate::die::{kept_flags, Advantage, Crit, CritRule, Die, DieKind, Keep, Pool, Reroll, Target};
+use crate::notation::{self, Roll, TermResult};
+use crate::outcomes::Interpreter;
+use crate::probability::{DieSpec, RollDefinition, TrayDie};
+use crate::roll_history::{RollEntry, RollGroup, RollSnapshot};
 
 mod imp {
 
@@ -9,10 +14,11 @@ mod imp {
         implement_vertex, index::PrimitiveType, program, uniform, Frame, IndexBuffer, Surface,
         VertexBuffer
     };
-    use gtk::{gio, glib, prelude::*, subclass::prelude::*};
+    use gtk::{gdk, gio, glib, prelude::*, subclass::prelude::*};
 
-    use crate::die::{Die, DieKind};
+    use crate::die::{kept_flags, Advantage, Crit, CritRule, Die, DieKind, Keep, Pool, Reroll, Target};
     use crate::preferences::hex_to_rgb;
+    use super::GROUP_COLORS;
 
     #[derive(Copy, Clone)]
     struct Vertex {
@@ -24,12 +30,26 @@ mod imp {
     struct Attr {
         // Fit both rotations and translations here
         world_matrix: [[f32; 4]; 4],
+        // 0.0 draws the die color, 1.0 fully desaturates it (dropped dice)
+        dim: f32,
+        // Group color mixed into the die color by its alpha
+        tint: [f32; 4],
+        // 1.0 lights the die evenly and brighter than the rest (crits)
+        glow: f32,
     }
-    implement_vertex!(Attr, world_matrix);
+    implement_vertex!(Attr, world_matrix, dim, tint, glow);
 
-    pub(super) const SPIN_DURATION: f32 = 1.5;
+    type DieBuffers<'a> = (&'a VertexBuffer<Vertex>, &'a IndexBuffer<u16>, &'a VertexBuffer<Attr>, usize);
+
+    pub const SPIN_DURATION: f32 = 1.5;
     pub(super) const REFLOW_DURATION: f32 = 0.3;
-    pub(super) const MAX_DICE: usize = 20;
+    pub const MAX_DICE: usize = 20;
+    pub(super) const MAX_MODIFIER: i32 = 99;
+    // Explosions and crit damage may grow the tray past MAX_DICE, up to this many dice
+    pub(super) const MAX_EXPLODED_DICE: usize = 40;
+    pub const MAX_EXPLODE_DEPTH: u32 = 10;
+    // Horizontal distance of each percentile D10 from the slot center, in die scales
+    const PERCENTILE_OFFSET: f32 = 0.55;
 
     // Builds a world matrix for the vec * mat shader convention (column-major).
     // Applies: result = Scale * Rz*Ry*Rx * position + Translation
@@ -51,7 +71,7 @@ mod imp {
         use std::f32::consts::FRAC_PI_2;
 
         // atan(1/sqrt(2)) — used for D4, D8, D20 cube-diagonal normals
-        const C1: f32 = 0.6154797086703874;
+        const C1: f32 = 0.615_479_7;
 
         match kind {
             DieKind::Six => {
@@ -137,7 +157,7 @@ mod imp {
             DieKind::Twelve => {
                 // Dodecahedron face normals: proportional to (0, ±φ, ±1) permutations
                 // Formula: ax = atan2(-ny, -nz), ay = atan2(nx, sqrt(ny²+nz²))
-                const PHI: f32 = 1.618033988749895;
+                const PHI: f32 = 1.618_034;
                 let a1: f32 = PHI.atan();           // atan(φ) ≈ 1.0172
                 let a2: f32 = (1.0 / PHI).atan();   // atan(1/φ) ≈ 0.5536
                 match val {
@@ -159,7 +179,7 @@ mod imp {
             DieKind::Twenty => {
                 // Icosahedron face normals computed from actual vertex positions
                 // Formula: ax = atan2(-ny, -nz), ay = atan2(nx, sqrt(ny²+nz²))
-                const PHI: f32 = 1.618033988749895;
+                const PHI: f32 = 1.618_034;
                 let b2: f32 = (1.0 / (PHI * PHI)).atan();  // atan(1/φ²) ≈ 0.3649
                 let b3: f32 = (PHI * PHI).atan();           // atan(φ²) ≈ 1.2059
                 match val {
@@ -186,9 +206,31 @@ mod imp {
                     _  => (0.0, 0.0, 0.0),
                 }
             }
+            // Matching symbols sit on opposite faces of the cube
+            DieKind::Fudge => {
+                let face = match val {
+                    1 => 1, 2 => 6,     // −
+                    3 => 2, 4 => 5,     // blank
+                    5 => 3, 6 => 4,     // +
+                    _ => 1,
+                };
+                settled_rotation(DieKind::Six, face)
+            }
+            DieKind::Hundred => settled_rotation(DieKind::Ten, percentile_faces(val).1),
+            // Tokens settle face-on; the value is shown by the label overlay
+            DieKind::Custom(_) => (0.0, 0.0, 0.0),
+            DieKind::Defined(_) => settled_rotation(kind.mesh(), val),
         }
     }
 
+    // Maps a percentile value to the (tens, units) faces of two D10 meshes.
+    // D10 face 10 reads as "0" on the units die and "00" on the tens die.
+    fn percentile_faces(val: u32) -> (u32, u32) {
+        let tens = (val / 10) % 10;
+        let units = val % 10;
+        (if tens == 0 { 10 } else { tens }, if units == 0 { 10 } else { units })
+    }
+
     fn die_scale(kind: DieKind) -> f32 {
         // Normalize so all dice appear the same visual size.
         // Factor = reference_radius / actual_bounding_radius
@@ -196,11 +238,14 @@ mod imp {
         const REF: f32 = 0.866;
         match kind {
             DieKind::Four   => REF / 0.866,  // 1.0
-            DieKind::Six    => REF / 0.866,  // 1.0
+            DieKind::Six | DieKind::Fudge => REF / 0.866,  // 1.0
             DieKind::Eight  => REF / 0.5,    // 1.732
             DieKind::Ten    => REF / 0.75,   // bounding radius = apex height
             DieKind::Twelve => REF / 0.866,  // 1.0
             DieKind::Twenty => REF / 0.951,  // 0.911
+            DieKind::Hundred => REF / 0.75 * 0.6, // two D10s share one slot
+            DieKind::Custom(_) => REF / 0.5,     // token radius
+            DieKind::Defined(_) => die_scale(kind.mesh()),
         }
     }
 
@@ -232,11 +277,22 @@ mod imp {
         twenty_index_buffer: IndexBuffer<u16>,
         twenty_per_instance: VertexBuffer<Attr>,
 
+        // Percentile dice reuse the D10 mesh but get their own color
+        hundred_per_instance: VertexBuffer<Attr>,
+
+        token_vertex_buffer: VertexBuffer<Vertex>,
+        token_index_buffer: IndexBuffer<u16>,
+        token_per_instance: VertexBuffer<Attr>,
+
         pub dice: Vec<Die>,
         prev_size: usize,
         prev_dimensions: (u32, u32),
+        prev_dims: Vec<f32>,
+        prev_crits: Vec<Option<Crit>>,
         pub die_screen_positions: Vec<(f32, f32, usize)>,
-        colors: [[f32; 3]; 6],
+        // Top center of each group's cluster, for its caption
+        pub group_screen_positions: Vec<(f32, f32, u32)>,
+        colors: [[f32; 3]; 8],
     }
 
     impl Renderer {
@@ -535,6 +591,38 @@ mod imp {
             let twenty_index_buffer =
                 IndexBuffer::new(&context, PrimitiveType::TrianglesList, &twenty_indices).unwrap();
 
+            // Token for dice without a polyhedron: a short cylinder facing the viewer.
+            // Vertex 0/1 are the front (-Z) and back centers, then front and back rings.
+            const TOKEN_SEGMENTS: u16 = 24;
+            let token_radius: f32 = 0.5;
+            let token_depth: f32 = 0.08;
+            let mut token_verts: Vec<Vertex> = vec![
+                Vertex { position: [0.0, 0.0, -token_depth] },
+                Vertex { position: [0.0, 0.0, token_depth] },
+            ];
+            for z in [-token_depth, token_depth] {
+                for i in 0..TOKEN_SEGMENTS {
+                    let angle = (i as f32) * 2.0 * PI / TOKEN_SEGMENTS as f32;
+                    token_verts.push(Vertex {
+                        position: [token_radius * angle.cos(), token_radius * angle.sin(), z],
+                    });
+                }
+            }
+            let mut token_indices: Vec<u16> = Vec::with_capacity(TOKEN_SEGMENTS as usize * 12);
+            for i in 0..TOKEN_SEGMENTS {
+                let front = 2 + i;
+                let front_next = 2 + (i + 1) % TOKEN_SEGMENTS;
+                let back = front + TOKEN_SEGMENTS;
+                let back_next = front_next + TOKEN_SEGMENTS;
+                token_indices.extend_from_slice(&[0, front, front_next]);
+                token_indices.extend_from_slice(&[1, back_next, back]);
+                token_indices.extend_from_slice(&[front, back, back_next]);
+                token_indices.extend_from_slice(&[back_next, front_next, front]);
+            }
+            let token_vertex_buffer = VertexBuffer::new(&context, &token_verts).unwrap();
+            let token_index_buffer =
+                IndexBuffer::new(&context, PrimitiveType::TrianglesList, &token_indices).unwrap();
+
 
             // TODO get the GResource state
             let four_per_instance: VertexBuffer<Attr> = VertexBuffer::empty_dynamic(&context, 0).unwrap();
@@ -543,6 +631,8 @@ mod imp {
             let ten_per_instance: VertexBuffer<Attr> = VertexBuffer::empty_dynamic(&context, 0).unwrap();
             let twelve_per_instance: VertexBuffer<Attr> = VertexBuffer::empty_dynamic(&context, 0).unwrap();
             let twenty_per_instance: VertexBuffer<Attr> = VertexBuffer::empty_dynamic(&context, 0).unwrap();
+            let hundred_per_instance: VertexBuffer<Attr> = VertexBuffer::empty_dynamic(&context, 0).unwrap();
+            let token_per_instance: VertexBuffer<Attr> = VertexBuffer::empty_dynamic(&context, 0).unwrap();
 
             let program = program!(&context,
                 // This example includes a shader that requires GLSL 1.40 or above.
@@ -566,18 +656,25 @@ mod imp {
                         #version 300 es
 
                         in mat4 world_matrix;
+                        in float dim;
+                        in vec4 tint;
+                        in float glow;
                         uniform mat4 perspective;
                         uniform vec3 die_color;
 
                         in vec3 position;
                         out vec3 vColor;
                         out vec3 vPosition;
+                        out float vGlow;
 
                         void main() {
                             vec4 worldPos = vec4(position, 1.0) * world_matrix;
                             gl_Position = worldPos * perspective;
-                            vColor = die_color;
+                            vec3 base = mix(die_color, tint.rgb, tint.a);
+                            vec3 grey = vec3(dot(base, vec3(0.299, 0.587, 0.114)));
+                            vColor = mix(base, grey, dim) * (1.0 - 0.5 * dim);
                             vPosition = worldPos.xyz;
+                            vGlow = glow;
                         }
                     ",
 
@@ -586,6 +683,7 @@ mod imp {
                         precision mediump float;
                         in vec3 vColor;
                         in vec3 vPosition;
+                        in float vGlow;
 
                         out vec4 f_color;
                         void main() {
@@ -593,7 +691,7 @@ mod imp {
                             vec3 lightDir = normalize(vec3(0.3, 0.5, 1.0));
                             float diffuse = abs(dot(normal, lightDir));
                             float ambient = 0.3;
-                            float lighting = ambient + (1.0 - ambient) * diffuse;
+                            float lighting = mix(ambient + (1.0 - ambient) * diffuse, 1.3, vGlow);
                             f_color = vec4(vColor * lighting, 1.0);
                         }
                     "
@@ -602,6 +700,9 @@ mod imp {
                     vertex: "
                         #version 150
                         in mat4 world_matrix;
+                        in float dim;
+                        in vec4 tint;
+                        in float glow;
                         uniform mat4 perspective;
                         uniform vec3 die_color;
 
@@ -609,12 +710,16 @@ mod imp {
 
                         out vec3 vColor;
                         out vec3 vPosition;
+                        out float vGlow;
 
                         void main() {
                             vec4 worldPos = vec4(position, 1.0) * world_matrix;
                             gl_Position = worldPos * perspective;
-                            vColor = die_color;
+                            vec3 base = mix(die_color, tint.rgb, tint.a);
+                            vec3 grey = vec3(dot(base, vec3(0.299, 0.587, 0.114)));
+                            vColor = mix(base, grey, dim) * (1.0 - 0.5 * dim);
                             vPosition = worldPos.xyz;
+                            vGlow = glow;
                         }
                     ",
 
@@ -622,13 +727,14 @@ mod imp {
                         #version 150
                         in vec3 vColor;
                         in vec3 vPosition;
+                        in float vGlow;
                         out vec4 f_color;
                         void main() {
                             vec3 normal = normalize(cross(dFdx(vPosition), dFdy(vPosition)));
                             vec3 lightDir = normalize(vec3(0.3, 0.5, 1.0));
                             float diffuse = abs(dot(normal, lightDir));
                             float ambient = 0.3;
-                            float lighting = ambient + (1.0 - ambient) * diffuse;
+                            float lighting = mix(ambient + (1.0 - ambient) * diffuse, 1.3, vGlow);
                             f_color = vec4(vColor * lighting, 1.0);
                         }
                     "
@@ -661,15 +767,22 @@ mod imp {
                 twenty_vertex_buffer,
                 twenty_index_buffer,
                 twenty_per_instance,
+                hundred_per_instance,
+                token_vertex_buffer,
+                token_index_buffer,
+                token_per_instance,
                 dice,
                 prev_size,
                 prev_dimensions: (0, 0),
+                prev_dims: Vec::new(),
+                prev_crits: Vec::new(),
                 die_screen_positions: Vec::new(),
+                group_screen_positions: Vec::new(),
                 colors,
             }
         }
 
-        fn load_colors_from_settings() -> [[f32; 3]; 6] {
+        fn load_colors_from_settings() -> [[f32; 3]; 8] {
             let settings = gio::Settings::new("org.lesslie.dice");
             [
                 hex_to_rgb(&settings.string("color-d4")),
@@ -678,6 +791,8 @@ mod imp {
                 hex_to_rgb(&settings.string("color-d10")),
                 hex_to_rgb(&settings.string("color-d12")),
                 hex_to_rgb(&settings.string("color-d20")),
+                hex_to_rgb(&settings.string("color-d100")),
+                hex_to_rgb(&settings.string("color-custom")),
             ]
         }
 
@@ -685,7 +800,59 @@ mod imp {
             self.colors = Self::load_colors_from_settings();
         }
 
+        // Shows the next face of any settled die its reroll rule rolled again
+        fn advance_rerolls(&self) {
+            for die in &self.dice {
+                let settled = die.time.get()
+                    .map(|t| t.elapsed().as_secs_f32() >= SPIN_DURATION)
+                    .unwrap_or(true);
+                if settled {
+                    die.advance_reroll();
+                }
+            }
+        }
+
+        // Spawns the next die of a chain next to any exploding die that settled on its highest face
+        fn explode_settled(&mut self) {
+            let mut i = 0;
+            while i < self.dice.len() && self.dice.len() < MAX_EXPLODED_DICE {
+                let die = &self.dice[i];
+                let settled = die.time.get()
+                    .map(|t| t.elapsed().as_secs_f32() >= SPIN_DURATION)
+                    .unwrap_or(true);
+                if let (true, true, Some((chain, depth))) = (settled, die.should_explode(), die.chain.get()) {
+                    let spawned = self.dice.iter().any(|d| d.chain.get() == Some((chain, depth + 1)));
+                    if !spawned && depth < MAX_EXPLODE_DEPTH {
+                        let extra = Die::new(die.kind);
+                        extra.explode.set(true);
+                        extra.chain.set(Some((chain, depth + 1)));
+                        extra.group.set(die.group.get());
+                        extra.sign.set(die.sign.get());
+                        extra.set_reroll_rule(die.reroll_rule.get());
+                        self.dice.insert(i + 1, extra);
+                    }
+                }
+                i += 1;
+            }
+        }
+
+        // Counts the face of every die that has just settled, before a reroll replaces it
+        fn tally_settled(&mut self) {
+            let faces: Vec<(DieKind, u32)> = self.dice.iter()
+                .filter(|die| !die.tallied.get() && die.time.get().is_none_or(|t| t.elapsed().as_secs_f32() >= SPIN_DURATION))
+                .map(|die| {
+                    die.tallied.set(true);
+                    (die.kind, die.val.get())
+                })
+                .collect();
+            crate::statistics::record(&faces);
+        }
+
         fn draw(&mut self) {
+            self.tally_settled();
+            self.advance_rerolls();
+            self.explode_settled();
+
             let mut frame = Frame::new(
                 self.context.clone(),
                 self.context.get_framebuffer_dimensions(),
@@ -732,17 +899,57 @@ mod imp {
                 spin_active || reflow_active
             });
 
+            // Dropped dice are only revealed once they stop spinning
+            let kept = kept_flags(&self.dice);
+            let dims: Vec<f32> = self.dice.iter().zip(&kept).map(|(die, &kept)| {
+                let settled = die.time.get()
+                    .map(|t| t.elapsed().as_secs_f32() >= SPIN_DURATION)
+                    .unwrap_or(true);
+                if settled && !kept { 1.0 } else { 0.0 }
+            }).collect();
+
+            // Crits are likewise only highlighted once they settle, and only on dice that count
+            let crits: Vec<Option<Crit>> = self.dice.iter().zip(&kept).map(|(die, &kept)| {
+                let settled = die.time.get()
+                    .map(|t| t.elapsed().as_secs_f32() >= SPIN_DURATION)
+                    .unwrap_or(true);
+                die.crit().filter(|_| settled && kept)
+            }).collect();
+
             let current_dimensions = self.context.get_framebuffer_dimensions();
-            if size != &self.prev_size || any_animating || current_dimensions != self.prev_dimensions {
+            if size != &self.prev_size || any_animating || current_dimensions != self.prev_dimensions || dims != self.prev_dims || crits != self.prev_crits {
                 let n = *size;
                 let viewport_width = 1.8f32;
                 let viewport_height = 1.6f32;
                 let base_scale = 0.4f32;
                 const MAX_PER_ROW: usize = 5;
 
-                // Grid layout: distribute dice across rows
-                let rows = if n == 0 { 1 } else { (n + MAX_PER_ROW - 1) / MAX_PER_ROW };
-                let cols = if n == 0 { 0 } else { (n + rows - 1) / rows }; // distribute evenly
+                // Grid layout: each group gets its own cluster of rows, stacked in the
+                // order the groups first appear, with its dice distributed evenly
+                let mut groups: Vec<u32> = Vec::new();
+                for die in &self.dice {
+                    if !groups.contains(&die.group.get()) {
+                        groups.push(die.group.get());
+                    }
+                }
+                let mut slots = vec![(0, 0, 0); n]; // (row, col, cols in that row)
+                let mut group_rows: Vec<(u32, usize)> = Vec::new();
+                let mut rows = 0;
+                let mut cols = 0;
+                for &group in &groups {
+                    let members: Vec<usize> = (0..n).filter(|&i| self.dice[i].group.get() == group).collect();
+                    let m = members.len();
+                    let cluster_rows = m.div_ceil(MAX_PER_ROW);
+                    let cluster_cols = m.div_ceil(cluster_rows);
+                    for (j, &i) in members.iter().enumerate() {
+                        let row = j / cluster_cols;
+                        slots[i] = (rows + row, j % cluster_cols, (m - row * cluster_cols).min(cluster_cols));
+                    }
+                    group_rows.push((group, rows));
+                    rows += cluster_rows;
+                    cols = cols.max(cluster_cols);
+                }
+                let rows = rows.max(1);
 
                 // Scale to fit both dimensions
                 let scale = if n <= 1 {
@@ -762,16 +969,23 @@ mod imp {
                 let mut ten_instances: Vec<Attr> = Vec::new();
                 let mut twelve_instances: Vec<Attr> = Vec::new();
                 let mut twenty_instances: Vec<Attr> = Vec::new();
+                let mut hundred_instances: Vec<Attr> = Vec::new();
+                let mut token_instances: Vec<Attr> = Vec::new();
 
                 let (width, height) = self.context.get_framebuffer_dimensions();
                 let aspect_ratio = height as f32 / width as f32;
 
                 self.die_screen_positions.clear();
+                self.group_screen_positions.clear();
+                for &(group, row) in &group_rows {
+                    let top = (rows as f32 - 1.0) * slot_height / 2.0 - row as f32 * slot_height + scale * 1.2;
+                    let screen_x = width as f32 / 2.0;
+                    let screen_y = (1.0 - top) / 2.0 * height as f32;
+                    self.group_screen_positions.push((screen_x, screen_y, group));
+                }
 
                 for (i, die) in self.dice.iter().enumerate() {
-                    let row = i / cols;
-                    let col = i % cols;
-                    let cols_this_row = (n - row * cols).min(cols);
+                    let (row, col, cols_this_row) = slots[i];
 
                     let target_x = -(cols_this_row as f32 - 1.0) * slot_width / 2.0 + col as f32 * slot_width;
                     let target_y = (rows as f32 - 1.0) * slot_height / 2.0 - row as f32 * slot_height;
@@ -810,27 +1024,64 @@ mod imp {
                     let t = (elapsed / SPIN_DURATION).min(1.0);
                     let eased = 1.0 - (1.0 - t).powi(3); // ease-out cubic
 
-                    let (settled_x, settled_y, settled_z) = settled_rotation(die.kind, die.val.get());
                     let seed = die.spin_seed.get();
 
-                    let angle_x = eased * (settled_x + seed[0] as f32 * 2.0 * PI);
-                    let angle_y = eased * (settled_y + seed[1] as f32 * 2.0 * PI);
-                    let angle_z = eased * (settled_z + seed[2] as f32 * 2.0 * PI);
+                    // Percentile dice are a tens d10 and a units d10 side by side
+                    let meshes = match die.kind {
+                        DieKind::Hundred => {
+                            let (tens, units) = percentile_faces(die.val.get());
+                            let offset = scale * PERCENTILE_OFFSET;
+                            vec![
+                                (DieKind::Ten, tens, -offset, seed),
+                                (DieKind::Ten, units, offset, [seed[1], seed[2], seed[0]]),
+                            ]
+                        }
+                        kind => vec![(kind, die.val.get(), 0.0, seed)],
+                    };
+
+                    for (mesh, face, dx, seed) in meshes {
+                        let (settled_x, settled_y, settled_z) = settled_rotation(mesh, face);
 
-                    let world = build_world_matrix(scale * die_scale(die.kind), x, y, angle_x, angle_y, angle_z);
+                        let angle_x = eased * (settled_x + seed[0] as f32 * 2.0 * PI);
+                        let angle_y = eased * (settled_y + seed[1] as f32 * 2.0 * PI);
+                        let angle_z = eased * (settled_z + seed[2] as f32 * 2.0 * PI);
 
-                    let screen_x = (x * aspect_ratio + 1.0) / 2.0 * width as f32;
-                    let screen_y = (1.0 - y) / 2.0 * height as f32;
-                    self.die_screen_positions.push((screen_x, screen_y, i));
+                        let world = build_world_matrix(scale * die_scale(die.kind), x + dx, y, angle_x, angle_y, angle_z);
 
-                    let attr = Attr { world_matrix: world };
-                    match die.kind {
-                        DieKind::Four => four_instances.push(attr),
-                        DieKind::Six => six_instances.push(attr),
-                        DieKind::Eight => eight_instances.push(attr),
-                        DieKind::Ten => ten_instances.push(attr),
-                        DieKind::Twelve => twelve_instances.push(attr),
-                        DieKind::Twenty => twenty_instances.push(attr),
+                        let screen_x = ((x + dx) * aspect_ratio + 1.0) / 2.0 * width as f32;
+                        let screen_y = (1.0 - y) / 2.0 * height as f32;
+                        self.die_screen_positions.push((screen_x, screen_y, i));
+
+                        // Crits and the extra dice of crit damage are tinted over their group
+                        // color, and crits glow so they stand out from any group
+                        let tint = match (crits[i], die.group.get()) {
+                            (Some(crit), _) => {
+                                let [r, g, b] = hex_to_rgb(super::crit_color(crit));
+                                [r, g, b, 0.6]
+                            }
+                            (None, _) if die.crit_extra.get() => {
+                                let [r, g, b] = hex_to_rgb(super::CRIT_EXTRA_COLOR);
+                                [r, g, b, 0.6]
+                            }
+                            (None, 0) => [0.0; 4],
+                            (None, group) => {
+                                let [r, g, b] = hex_to_rgb(GROUP_COLORS[(group as usize - 1) % GROUP_COLORS.len()]);
+                                [r, g, b, 0.45]
+                            }
+                        };
+                        let glow = if crits[i].is_some() { 1.0 } else { 0.0 };
+                        let attr = Attr { world_matrix: world, dim: dims[i], tint, glow };
+                        // User-defined dice are drawn with the mesh and color of their base kind
+                        match die.kind.mesh() {
+                            DieKind::Four => four_instances.push(attr),
+                            DieKind::Six | DieKind::Fudge => six_instances.push(attr),
+                            DieKind::Eight => eight_instances.push(attr),
+                            DieKind::Ten => ten_instances.push(attr),
+                            DieKind::Twelve => twelve_instances.push(attr),
+                            DieKind::Twenty => twenty_instances.push(attr),
+                            DieKind::Hundred => hundred_instances.push(attr),
+                            DieKind::Custom(_) | DieKind::Defined(_) => token_instances.push(attr),
+                        }
                     }
                 }
 
@@ -864,9 +1115,21 @@ mod imp {
                 } else {
                     VertexBuffer::dynamic(&self.context, &twenty_instances).unwrap()
                 };
+                self.hundred_per_instance = if hundred_instances.is_empty() {
+                    VertexBuffer::empty_dynamic(&self.context, 0).unwrap()
+                } else {
+                    VertexBuffer::dynamic(&self.context, &hundred_instances).unwrap()
+                };
+                self.token_per_instance = if token_instances.is_empty() {
+                    VertexBuffer::empty_dynamic(&self.context, 0).unwrap()
+                } else {
+                    VertexBuffer::dynamic(&self.context, &token_instances).unwrap()
+                };
 
                 self.prev_size = *size;
                 self.prev_dimensions = current_dimensions;
+                self.prev_dims = dims;
+                self.prev_crits = crits;
             }
 
             let params = glium::DrawParameters::default();
@@ -896,13 +1159,15 @@ mod imp {
                 clear(GL_DEPTH_BUFFER_BIT);
             }
 
-            let die_types: [(&VertexBuffer<Vertex>, &IndexBuffer<u16>, &VertexBuffer<Attr>, usize); 6] = [
+            let die_types: [DieBuffers; 8] = [
                 (&self.four_vertex_buffer, &self.four_index_buffer, &self.four_per_instance, 0),
                 (&self.six_vertex_buffer, &self.six_index_buffer, &self.six_per_instance, 1),
                 (&self.eight_vertex_buffer, &self.eight_index_buffer, &self.eight_per_instance, 2),
                 (&self.ten_vertex_buffer, &self.ten_index_buffer, &self.ten_per_instance, 3),
                 (&self.twelve_vertex_buffer, &self.twelve_index_buffer, &self.twelve_per_instance, 4),
                 (&self.twenty_vertex_buffer, &self.twenty_index_buffer, &self.twenty_per_instance, 5),
+                (&self.ten_vertex_buffer, &self.ten_index_buffer, &self.hundred_per_instance, 6),
+                (&self.token_vertex_buffer, &self.token_index_buffer, &self.token_per_instance, 7),
             ];
 
             for (vb, ib, inst, color_idx) in die_types {
@@ -930,6 +1195,48 @@ mod imp {
     pub struct DiceArea {
         pub renderer: RefCell<Option<Renderer>>,
         pub colors_dirty: Cell<bool>,
+        pub expression: RefCell<Option<String>>,
+        pub modifier: Cell<i32>,
+        pub keep: Cell<Option<Keep>>,
+        pub advantage: Cell<Option<Advantage>>,
+        pub pool: Cell<Option<Pool>>,
+        pub target: Cell<Option<Target>>,
+        pub reroll_rule: Cell<Option<Reroll>>,
+        pub groups: RefCell<Vec<super::GroupInfo>>,
+        pub repeats: Cell<u32>,
+        pub crit_rule: Cell<Option<CritRule>>,
+        pub interpreter: RefCell<Option<crate::outcomes::Interpreter>>,
+        // When set, clicking a die selects it instead of removing it
+        pub selecting: Cell<bool>,
+        pub die_menu: RefCell<Option<gtk::PopoverMenu>>,
+        pub menu_die: Cell<Option<usize>>,
+    }
+
+    impl DiceArea {
+        // Index of the die drawn nearest to a point in widget coordinates
+        fn die_at(&self, x: f64, y: f64) -> Option<usize> {
+            let scale = self.obj().scale_factor() as f32;
+            let click_x = x as f32 * scale;
+            let click_y = y as f32 * scale;
+
+            let binding = self.renderer.borrow();
+            let renderer = binding.as_ref()?;
+            let threshold = 80.0f32;
+            renderer.die_screen_positions.iter()
+                .map(|&(sx, sy, idx)| (((click_x - sx).powi(2) + (click_y - sy).powi(2)).sqrt(), idx))
+                .filter(|&(dist, idx)| dist < threshold && idx < renderer.dice.len())
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, idx)| idx)
+        }
+
+        fn show_die_menu(&self, x: f64, y: f64) {
+            let Some(idx) = self.die_at(x, y) else { return };
+            self.menu_die.set(Some(idx));
+            if let Some(menu) = self.die_menu.borrow().as_ref() {
+                menu.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+                menu.popup();
+            }
+        }
     }
 
     #[glib::object_subclass]
@@ -944,7 +1251,7 @@ mod imp {
             self.parent_constructed();
 
             let settings = gio::Settings::new("org.lesslie.dice");
-            for key in &["color-d4", "color-d6", "color-d8", "color-d10", "color-d12", "color-d20"] {
+            for key in &["color-d4", "color-d6", "color-d8", "color-d10", "color-d12", "color-d20", "color-d100", "color-custom"] {
                 settings.connect_changed(
                     Some(key),
                     glib::clone!(#[weak(rename_to = this)] self, move |_settings, _key| {
@@ -958,39 +1265,81 @@ mod imp {
                 glib::ControlFlow::Continue
             });
 
+            // Released rather than pressed, so a long press can claim the touch first
             let click = gtk::GestureClick::new();
-            click.connect_pressed(glib::clone!(#[weak(rename_to = this)] self, move |_gesture, _n, x, y| {
-                let widget = this.obj();
-                let scale = widget.scale_factor() as f32;
-                let click_x = x as f32 * scale;
-                let click_y = y as f32 * scale;
-
-                let mut binding = this.renderer.borrow_mut();
-                if let Some(renderer) = binding.as_mut() {
-                    let threshold = 80.0f32;
-                    let mut closest: Option<(f32, usize)> = None;
-
-                    for &(sx, sy, idx) in &renderer.die_screen_positions {
-                        let dist = ((click_x - sx).powi(2) + (click_y - sy).powi(2)).sqrt();
-                        if dist < threshold {
-                            if closest.is_none() || dist < closest.unwrap().0 {
-                                closest = Some((dist, idx));
-                            }
-                        }
-                    }
-
-                    if let Some((_, idx)) = closest {
-                        if idx < renderer.dice.len() {
-                            renderer.dice.remove(idx);
-                        }
-                    }
+            click.connect_released(glib::clone!(#[weak(rename_to = this)] self, move |_gesture, _n, x, y| {
+                let Some(idx) = this.die_at(x, y) else { return };
+                if this.selecting.get() {
+                    this.obj().toggle_selected(idx);
+                } else {
+                    this.obj().remove_die(idx);
                 }
             }));
             self.obj().add_controller(click);
+
+            let secondary_click = gtk::GestureClick::new();
+            secondary_click.set_button(gdk::BUTTON_SECONDARY);
+            secondary_click.connect_pressed(glib::clone!(#[weak(rename_to = this)] self, move |_gesture, _n, x, y| {
+                this.show_die_menu(x, y);
+            }));
+            self.obj().add_controller(secondary_click);
+
+            let long_press = gtk::GestureLongPress::new();
+            long_press.set_touch_only(true);
+            long_press.connect_pressed(glib::clone!(#[weak(rename_to = this)] self, move |_gesture, x, y| {
+                this.show_die_menu(x, y);
+            }));
+            self.obj().add_controller(long_press);
+
+            let menu = gio::Menu::new();
+            menu.append(Some("Reroll"), Some("die.reroll"));
+            menu.append(Some("Select"), Some("die.select"));
+            menu.append(Some("Remove"), Some("die.remove"));
+            let popover = gtk::PopoverMenu::from_model(Some(&menu));
+            popover.set_parent(&*self.obj());
+            popover.set_has_arrow(false);
+            self.die_menu.replace(Some(popover));
+
+            let actions = gio::SimpleActionGroup::new();
+            let action = gio::SimpleAction::new("reroll", None);
+            action.connect_activate(glib::clone!(#[weak(rename_to = this)] self, move |_, _| {
+                if let Some(idx) = this.menu_die.take() {
+                    this.obj().reroll_dice(&[idx]);
+                }
+            }));
+            actions.add_action(&action);
+            let action = gio::SimpleAction::new("select", None);
+            action.connect_activate(glib::clone!(#[weak(rename_to = this)] self, move |_, _| {
+                if let Some(idx) = this.menu_die.take() {
+                    this.obj().toggle_selected(idx);
+                }
+            }));
+            actions.add_action(&action);
+            let action = gio::SimpleAction::new("remove", None);
+            action.connect_activate(glib::clone!(#[weak(rename_to = this)] self, move |_, _| {
+                if let Some(idx) = this.menu_die.take() {
+                    this.obj().remove_die(idx);
+                }
+            }));
+            actions.add_action(&action);
+            self.obj().insert_action_group("die", Some(&actions));
+        }
+
+        fn dispose(&self) {
+            if let Some(menu) = self.die_menu.take() {
+                menu.unparent();
+            }
         }
     }
 
     impl WidgetImpl for DiceArea {
+        fn size_allocate(&self, width: i32, height: i32, baseline: i32) {
+            self.parent_size_allocate(width, height, baseline);
+            if let Some(menu) = self.die_menu.borrow().as_ref() {
+                menu.present();
+            }
+        }
+
         fn realize(&self) {
             self.parent_realize();
 
@@ -1035,6 +1384,50 @@ mod imp {
 }
 
 
+pub use imp::{MAX_DICE, MAX_EXPLODE_DEPTH, SPIN_DURATION};
+
+// Repetitions of one notation roll, each drawn as its own group
+pub const MAX_REPEATS: u32 = 20;
+
+// Keep set id used for rules chosen from the toolbar rather than notation
+const TRAY_KEEP_SET: u32 = 0;
+
+// Kept out of GROUP_COLORS, so a crit never looks like an ordinary grouped die
+pub fn crit_color(crit: Crit) -> &'static str {
+    match crit {
+        Crit::Success => "#00C2C7",
+        Crit::Failure => "#63452C",
+    }
+}
+
+// Tint of the dice a crit damage rule adds
+pub const CRIT_EXTRA_COLOR: &str = "#C061CB";
+
+// Tints for named groups, in the order they appear in the tray
+pub const GROUP_COLORS: [&str; 6] = ["#E01B24", "#3584E4", "#2EC27E", "#F6D32D", "#9141AC", "#FF7800"];
+
+#[derive(Clone)]
+pub struct GroupInfo {
+    pub name: String,
+    pub modifier: i32,
+}
+
+// A die that has finished spinning, with a label position for each mesh it draws
+pub struct SettledDie {
+    pub kind: DieKind,
+    pub value: i32,
+    pub symbols: Vec<String>,
+    pub kept: bool,
+    pub group: u32,
+    pub advantage: Option<Advantage>,
+    pub crit: Option<Crit>,
+    pub crit_extra: bool,
+    pub selected: bool,
+    // What the die adds in pool mode; always 0 outside it
+    pub successes: i32,
+    pub faces: Vec<(f32, f32, String)>,
+}
+
 glib::wrapper! {
     pub struct DiceArea(ObjectSubclass<imp::DiceArea>)
         @extends gtk::GLArea, gtk::Widget,
@@ -1092,95 +1485,418 @@ impl DiceArea {
     }
 
     pub fn add_four(&self) {
-        let imp = self.imp();
+        self.add_dice([Die::new(DieKind::Four)]);
+    }
 
-        let mut binding = imp.renderer.borrow_mut();
-        if let Some(renderer) = binding.as_mut() {
-            if renderer.dice.len() >= imp::MAX_DICE { return; }
-            renderer.dice.push(Die::new(DieKind::Four));
-        } else {
-            println!("Renderer doesn't exist");
+    pub fn add_six(&self) {
+        self.add_dice([Die::new(DieKind::Six)]);
+    }
+
+    pub fn add_fudge(&self) {
+        self.add_dice([Die::new(DieKind::Fudge)]);
+    }
+
+    pub fn add_eight(&self) {
+        self.add_dice([Die::new(DieKind::Eight)]);
+    }
+
+    pub fn add_ten(&self) {
+        self.add_dice([Die::new(DieKind::Ten)]);
+    }
+
+    pub fn add_twelve(&self) {
+        self.add_dice([Die::new(DieKind::Twelve)]);
+    }
+
+    pub fn add_twenty(&self) {
+        let Some(advantage) = self.imp().advantage.get() else {
+            self.add_dice([Die::new(DieKind::Twenty)]);
+            return;
+        };
+
+        if self.free_slots() < 2 { return; }
+        let set = self.next_keep_set();
+        let pair = [Die::new(DieKind::Twenty), Die::new(DieKind::Twenty)];
+        for die in &pair {
+            die.keep.set(Some((set, advantage.keep())));
+            die.advantage.set(Some(advantage));
         }
+        self.add_dice(pair);
     }
 
-    pub fn add_six(&self) {
-        let imp = self.imp();
+    pub fn set_advantage(&self, advantage: Option<Advantage>) {
+        self.imp().advantage.set(advantage);
+    }
 
-        let mut binding = imp.renderer.borrow_mut();
-        if let Some(renderer) = binding.as_mut() {
-            if renderer.dice.len() >= imp::MAX_DICE { return; }
-            renderer.dice.push(Die::new(DieKind::Six));
-        } else {
-            println!("Renderer doesn't exist");
+    fn free_slots(&self) -> usize {
+        let binding = self.imp().renderer.borrow();
+        binding.as_ref().map(|r| imp::MAX_DICE.saturating_sub(r.dice.len())).unwrap_or(0)
+    }
+
+    // A keep set id not used by any die currently in the tray
+    fn next_keep_set(&self) -> u32 {
+        let binding = self.imp().renderer.borrow();
+        binding.as_ref()
+            .and_then(|r| r.dice.iter().filter_map(|d| d.keep.get()).map(|(set, _)| set).max())
+            .unwrap_or(TRAY_KEEP_SET) + 1
+    }
+
+    pub fn add_hundred(&self) {
+        self.add_dice([Die::new(DieKind::Hundred)]);
+    }
+
+    pub fn add_defined(&self, id: u32) {
+        self.add_dice([Die::new(DieKind::Defined(id))]);
+    }
+
+    pub fn add_custom(&self, sides: u32) {
+        if let Some(kind) = DieKind::from_sides(sides) {
+            self.add_dice([Die::new(kind)]);
         }
     }
 
-    pub fn add_eight(&self) {
+    pub fn add_dice(&self, dice: impl IntoIterator<Item = Die>) {
+        self.add_dice_up_to(dice, imp::MAX_DICE);
+    }
+
+    // Adds dice until the tray holds `limit` of them
+    fn add_dice_up_to(&self, dice: impl IntoIterator<Item = Die>, limit: usize) {
         let imp = self.imp();
 
         let mut binding = imp.renderer.borrow_mut();
         if let Some(renderer) = binding.as_mut() {
-            if renderer.dice.len() >= imp::MAX_DICE { return; }
-            renderer.dice.push(Die::new(DieKind::Eight));
+            let mut next_chain = renderer.dice.iter()
+                .filter_map(|d| d.chain.get())
+                .map(|(chain, _)| chain + 1)
+                .max()
+                .unwrap_or(0);
+            for die in dice {
+                if renderer.dice.len() >= limit { break; }
+                if let (Some(keep), None) = (imp.keep.get(), die.keep.get()) {
+                    die.keep.set(Some((TRAY_KEEP_SET, keep)));
+                }
+                if let (Some(rule), None) = (imp.reroll_rule.get(), die.reroll_rule.get()) {
+                    die.set_reroll_rule(Some(rule));
+                }
+                if die.explode.get() && die.chain.get().is_none() {
+                    die.chain.set(Some((next_chain, 0)));
+                    next_chain += 1;
+                }
+                renderer.dice.push(die);
+            }
+            imp.expression.replace(None);
         } else {
             println!("Renderer doesn't exist");
         }
     }
 
-    pub fn add_ten(&self) {
+    // Rolls the notation `repeats` times, each repetition in groups of its own.
+    // Leaves the tray alone if the dice wouldn't all fit in it.
+    pub fn roll_expression(&self, roll: &Roll, repeats: u32) -> Result<(), String> {
+        let repeats = repeats.clamp(1, MAX_REPEATS);
+        let needed = roll.dice_count() as usize * repeats as usize;
+        if needed > imp::MAX_DICE {
+            return Err(format!("Rolls {} dice, but only {} fit in the tray", needed, imp::MAX_DICE));
+        }
+        let batch: Vec<notation::Group> = (1..=repeats)
+            .flat_map(|k| roll.groups.iter().map(move |group| match (&group.name, repeats) {
+                (_, 1) => group.clone(),
+                (Some(name), _) => notation::Group { name: Some(format!("{} #{}", name, k)), expr: group.expr.clone() },
+                (None, _) => notation::Group { name: Some(format!("#{}", k)), expr: group.expr.clone() },
+            }))
+            .collect();
+
+        // A single unnamed group rolls straight into the tray, without group tints or captions
+        let grouped = batch.len() > 1 || batch.iter().any(|g| g.name.is_some());
+
+        self.clear();
+        let mut first_set = 1;
+        let mut groups = Vec::new();
+        let mut tray_modifier = 0;
+        for (i, group) in batch.iter().enumerate() {
+            let result = group.expr.evaluate(first_set);
+            first_set += group.expr.terms.len() as u32;
+            let modifier = result.terms.iter()
+                .filter_map(|term| match term {
+                    TermResult::Constant(n) => Some(*n),
+                    TermResult::Dice { .. } => None,
+                })
+                .sum::<i32>()
+                .clamp(-imp::MAX_MODIFIER, imp::MAX_MODIFIER);
+            let dice: Vec<Die> = result.terms.into_iter().flat_map(|term| match term {
+                TermResult::Dice { dice, .. } => dice,
+                TermResult::Constant(_) => Vec::new(),
+            }).collect();
+
+            if grouped {
+                for die in &dice {
+                    die.group.set(i as u32 + 1);
+                }
+                let name = group.name.clone().unwrap_or_else(|| format!("Group {}", i + 1));
+                groups.push(GroupInfo { name, modifier });
+            } else {
+                tray_modifier = modifier;
+            }
+            self.add_dice(dice);
+        }
+
         let imp = self.imp();
+        imp.groups.replace(groups);
+        imp.expression.replace(Some(roll.to_string()));
+        imp.modifier.set(tray_modifier);
+        imp.repeats.set(repeats);
+        Ok(())
+    }
 
-        let mut binding = imp.renderer.borrow_mut();
-        if let Some(renderer) = binding.as_mut() {
-            if renderer.dice.len() >= imp::MAX_DICE { return; }
-            renderer.dice.push(Die::new(DieKind::Ten));
-        } else {
-            println!("Renderer doesn't exist");
+    // How many times the notation in the tray was rolled
+    pub fn repeats(&self) -> u32 {
+        self.imp().repeats.get().max(1)
+    }
+
+    pub fn crit_rule(&self) -> Option<CritRule> {
+        self.imp().crit_rule.get()
+    }
+
+    // Turns the dice in the tray into critical damage, once. The extra dice go
+    // in under the explosion allowance, and the rule is refused if they don't
+    // all fit.
+    pub fn apply_crit(&self, rule: CritRule) {
+        let imp = self.imp();
+        if imp.crit_rule.get().is_some() {
+            return;
         }
+
+        let extras: Vec<Die> = match rule {
+            CritRule::DoubleTotal => Vec::new(),
+            CritRule::DoubleDice | CritRule::MaxPlusRoll => {
+                let binding = imp.renderer.borrow();
+                let dice: &[Die] = binding.as_ref().map(|r| r.dice.as_slice()).unwrap_or_default();
+                dice.iter()
+                    .filter(|d| !d.is_explosion())
+                    .map(|d| {
+                        let extra = Die::new(d.kind);
+                        if rule == CritRule::MaxPlusRoll {
+                            extra.val.set(d.kind.max_face());
+                            extra.tallied.set(true);
+                        } else {
+                            extra.explode.set(d.explode.get());
+                        }
+                        extra.set_reroll_rule(d.reroll_rule.get());
+                        extra.group.set(d.group.get());
+                        extra.sign.set(d.sign.get());
+                        extra.crit_extra.set(true);
+                        extra
+                    })
+                    .collect()
+            }
+        };
+        let in_tray = imp.renderer.borrow().as_ref().map(|r| r.dice.len()).unwrap_or(0);
+        if in_tray + extras.len() > imp::MAX_EXPLODED_DICE {
+            return;
+        }
+        self.add_dice_up_to(extras, imp::MAX_EXPLODED_DICE);
+        imp.expression.replace(None);
+        imp.crit_rule.set(Some(rule));
     }
-    pub fn add_twelve(&self) {
+
+    // The total of each named group, including dice that are still spinning
+    pub fn group_totals(&self) -> Vec<i32> {
         let imp = self.imp();
+        let binding = imp.renderer.borrow();
+        let dice: &[Die] = binding.as_ref().map(|r| r.dice.as_slice()).unwrap_or_default();
+        let kept = kept_flags(dice);
+        imp.groups.borrow().iter().enumerate()
+            .map(|(i, info)| {
+                let sum: i32 = dice.iter().zip(&kept)
+                    .filter(|&(d, &kept)| kept && d.group.get() == i as u32 + 1)
+                    .map(|(d, _)| d.signed_value())
+                    .sum();
+                sum + info.modifier
+            })
+            .collect()
+    }
 
-        let mut binding = imp.renderer.borrow_mut();
-        if let Some(renderer) = binding.as_mut() {
-            if renderer.dice.len() >= imp::MAX_DICE { return; }
-            renderer.dice.push(Die::new(DieKind::Twelve));
-        } else {
-            println!("Renderer doesn't exist");
+    // Named groups in the tray; a die in group N belongs to the entry at N - 1
+    pub fn groups(&self) -> Vec<GroupInfo> {
+        self.imp().groups.borrow().clone()
+    }
+
+    // Where each group's caption goes, in widget coordinates
+    pub fn group_captions(&self) -> Vec<(f32, f32, u32)> {
+        let binding = self.imp().renderer.borrow();
+        let scale_factor = self.scale_factor() as f32;
+        binding.as_ref()
+            .map(|r| r.group_screen_positions.iter()
+                .filter(|&&(_, _, group)| group > 0)
+                .map(|&(sx, sy, group)| (sx / scale_factor, sy / scale_factor, group))
+                .collect())
+            .unwrap_or_default()
+    }
+
+    // Applies a keep/drop rule across the whole tray, replacing any from notation
+    pub fn set_keep(&self, keep: Option<Keep>) {
+        let imp = self.imp();
+        imp.keep.set(keep);
+
+        let binding = imp.renderer.borrow();
+        if let Some(renderer) = binding.as_ref() {
+            for die in renderer.dice.iter().filter(|d| !d.is_explosion()) {
+                die.keep.set(keep.map(|keep| (TRAY_KEEP_SET, keep)));
+                die.advantage.set(None);
+            }
+            if !renderer.dice.is_empty() {
+                imp.expression.replace(None);
+            }
         }
     }
 
-    pub fn add_twenty(&self) {
+    pub fn pool(&self) -> Option<Pool> {
+        self.imp().pool.get()
+    }
+
+    pub fn set_pool(&self, pool: Option<Pool>) {
+        self.imp().pool.set(pool);
+    }
+
+    pub fn interpreter(&self) -> Option<Interpreter> {
+        self.imp().interpreter.borrow().clone()
+    }
+
+    pub fn set_interpreter(&self, interpreter: Option<Interpreter>) {
+        self.imp().interpreter.replace(interpreter);
+    }
+
+    pub fn target(&self) -> Option<Target> {
+        self.imp().target.get()
+    }
+
+    pub fn set_target(&self, target: Option<Target>) {
+        self.imp().target.set(target);
+    }
+
+    // Applies a reroll rule to the whole tray from its next roll on
+    pub fn set_reroll_rule(&self, rule: Option<Reroll>) {
         let imp = self.imp();
+        imp.reroll_rule.set(rule);
 
-        let mut binding = imp.renderer.borrow_mut();
-        if let Some(renderer) = binding.as_mut() {
-            if renderer.dice.len() >= imp::MAX_DICE { return; }
-            renderer.dice.push(Die::new(DieKind::Twenty));
-        } else {
-            println!("Renderer doesn't exist");
+        let binding = imp.renderer.borrow();
+        if let Some(renderer) = binding.as_ref() {
+            for die in &renderer.dice {
+                die.reroll_rule.set(rule);
+            }
+            if !renderer.dice.is_empty() {
+                imp.expression.replace(None);
+            }
         }
     }
 
+    pub fn modifier(&self) -> i32 {
+        self.imp().modifier.get()
+    }
+
+    pub fn set_modifier(&self, modifier: i32) {
+        let imp = self.imp();
+        imp.modifier.set(modifier.clamp(-imp::MAX_MODIFIER, imp::MAX_MODIFIER));
+        imp.expression.replace(None);
+    }
+
     pub fn roll(&self) {
         let imp = self.imp();
 
         let mut binding = imp.renderer.borrow_mut();
         if let Some(renderer) = binding.as_mut() {
+            // Chains start over from the dice that were added to the tray
+            renderer.dice.retain(|d| !d.is_explosion());
             for die in renderer.dice.iter_mut() {
                 die.roll();
+                die.rerolled.borrow_mut().clear();
+                die.selected.set(false);
+                if die.crit_extra.get() && imp.crit_rule.get() == Some(CritRule::MaxPlusRoll) {
+                    die.val.set(die.kind.max_face());
+                    die.pending_rerolls.borrow_mut().clear();
+                    die.tallied.set(true);
+                }
             }
         } else {
             println!("Renderer doesn't exist");
         }
     }
 
+    // Rerolls only the given dice, restarting any explosion that followed them
+    pub fn reroll_dice(&self, indices: &[usize]) {
+        let mut binding = self.imp().renderer.borrow_mut();
+        let Some(renderer) = binding.as_mut() else { return };
+
+        let mut restarted: Vec<(u32, u32)> = Vec::new();
+        for &i in indices {
+            if let Some(die) = renderer.dice.get(i) {
+                die.reroll();
+                die.selected.set(false);
+                restarted.extend(die.chain.get());
+            }
+        }
+        renderer.dice.retain(|d| match d.chain.get() {
+            Some((chain, depth)) => !restarted.iter().any(|&(c, from)| c == chain && depth > from),
+            None => true,
+        });
+    }
+
+    pub fn has_selection(&self) -> bool {
+        let binding = self.imp().renderer.borrow();
+        binding.as_ref().is_some_and(|r| r.dice.iter().any(|d| d.selected.get()))
+    }
+
+    pub fn reroll_selected(&self) {
+        let selected: Vec<usize> = {
+            let binding = self.imp().renderer.borrow();
+            binding.as_ref()
+                .map(|r| r.dice.iter().enumerate().filter(|(_, d)| d.selected.get()).map(|(i, _)| i).collect())
+                .unwrap_or_default()
+        };
+        self.reroll_dice(&selected);
+    }
+
+    pub fn set_selecting(&self, selecting: bool) {
+        let imp = self.imp();
+        imp.selecting.set(selecting);
+        if !selecting {
+            if let Some(renderer) = imp.renderer.borrow().as_ref() {
+                for die in &renderer.dice {
+                    die.selected.set(false);
+                }
+            }
+        }
+    }
+
+    fn toggle_selected(&self, idx: usize) {
+        if let Some(die) = self.imp().renderer.borrow().as_ref().and_then(|r| r.dice.get(idx)) {
+            die.selected.set(!die.selected.get());
+        }
+    }
+
+    fn remove_die(&self, idx: usize) {
+        let mut binding = self.imp().renderer.borrow_mut();
+        let Some(renderer) = binding.as_mut() else { return };
+
+        // Removing any die of an explosion removes the whole chain
+        match renderer.dice.get(idx).and_then(|d| d.chain.get()) {
+            Some((chain, _)) => renderer.dice.retain(|d| d.chain.get().is_none_or(|(c, _)| c != chain)),
+            None if idx < renderer.dice.len() => { renderer.dice.remove(idx); }
+            None => {}
+        }
+    }
+
     pub fn clear(&self) {
         let imp = self.imp();
 
         let mut binding = imp.renderer.borrow_mut();
         if let Some(renderer) = binding.as_mut() {
             renderer.dice.clear();
+            imp.expression.replace(None);
+            imp.modifier.set(0);
+            imp.groups.borrow_mut().clear();
+            imp.repeats.set(1);
+            imp.crit_rule.set(None);
         } else {
             println!("Renderer doesn't exist");
         }
@@ -1196,46 +1912,249 @@ impl DiceArea {
         }
     }
 
-    pub fn settled_dice_info(&self) -> Vec<(f32, f32, u32)> {
+    pub fn settled_dice_info(&self) -> Vec<SettledDie> {
         let imp_ref = self.imp();
         let binding = imp_ref.renderer.borrow();
         let scale_factor = self.scale_factor() as f32;
         if let Some(renderer) = binding.as_ref() {
             let positions = &renderer.die_screen_positions;
+            let kept = kept_flags(&renderer.dice);
             renderer.dice.iter().enumerate().filter_map(|(i, die)| {
                 let elapsed = die.time.get()
                     .map(|t| t.elapsed().as_secs_f32())
                     .unwrap_or(imp::SPIN_DURATION);
-                if elapsed >= imp::SPIN_DURATION {
-                    positions.iter()
-                        .find(|&&(_, _, idx)| idx == i)
-                        .map(|&(sx, sy, _)| (sx / scale_factor, sy / scale_factor, die.val.get()))
-                } else {
-                    None
+                if elapsed < imp::SPIN_DURATION {
+                    return None;
+                }
+
+                let faces: Vec<(f32, f32, String)> = positions.iter()
+                    .filter(|&&(_, _, idx)| idx == i)
+                    .zip(die.face_labels())
+                    .map(|(&(sx, sy, _), label)| (sx / scale_factor, sy / scale_factor, label))
+                    .collect();
+                if faces.is_empty() {
+                    return None;
                 }
+
+                Some(SettledDie {
+                    kind: die.kind,
+                    value: die.signed_value(),
+                    symbols: custom_dice::symbols(die.kind, die.val.get()),
+                    kept: kept[i],
+                    group: die.group.get(),
+                    advantage: die.advantage.get(),
+                    crit: die.crit(),
+                    crit_extra: die.crit_extra.get(),
+                    selected: die.selected.get(),
+                    successes: imp_ref.pool.get().map_or(0, |pool| die.sign.get() * pool.successes(die.val.get())),
+                    faces,
+                })
             }).collect()
         } else {
             Vec::new()
         }
     }
 
-    pub fn dice_snapshot(&self) -> Vec<(DieKind, u32)> {
+    // How the dice in the tray are rolled, for working out the odds of their total
+    pub fn roll_definition(&self) -> RollDefinition {
+        let imp = self.imp();
+        let binding = imp.renderer.borrow();
+        let dice: &[Die] = binding.as_ref().map(|r| r.dice.as_slice()).unwrap_or_default();
+        let max_plus_roll = imp.crit_rule.get() == Some(CritRule::MaxPlusRoll);
+
+        let tray = dice.iter()
+            .filter(|d| !d.is_explosion())
+            .map(|die| TrayDie {
+                keep: die.keep.get(),
+                sign: die.sign.get(),
+                spec: DieSpec {
+                    kind: die.kind,
+                    explode: die.explode.get(),
+                    reroll: die.reroll_rule.get(),
+                    fixed: max_plus_roll && die.crit_extra.get(),
+                },
+            });
+        RollDefinition::from_tray(
+            tray,
+            imp.modifier.get() + imp.groups.borrow().iter().map(|info| info.modifier).sum::<i32>(),
+            imp.crit_rule.get() == Some(CritRule::DoubleTotal),
+            imp.pool.get(),
+        )
+    }
+
+    pub fn dice_snapshot(&self) -> RollSnapshot {
         let imp_ref = self.imp();
         let binding = imp_ref.renderer.borrow();
-        binding.as_ref().map(|r| {
-            r.dice.iter().map(|d| (d.kind, d.val.get())).collect()
-        }).unwrap_or_default()
+        let dice: &[Die] = binding.as_ref().map(|r| r.dice.as_slice()).unwrap_or_default();
+
+        // Extra dice from explosions are recorded as part of the die that started each chain
+        let kept = kept_flags(dice);
+        let starts: Vec<(&Die, bool)> = dice.iter().zip(kept)
+            .filter(|(d, _)| !d.is_explosion())
+            .collect();
+
+        // Only record a tray-wide rule when every die outside an advantage pair follows it
+        let mut others = starts.iter().map(|&(d, _)| d).filter(|d| d.advantage.get().is_none()).peekable();
+        let keep = match others.peek().and_then(|d| d.keep.get()) {
+            Some((TRAY_KEEP_SET, keep)) if others.all(|d| d.keep.get() == Some((TRAY_KEEP_SET, keep))) => Some(keep),
+            _ => None,
+        };
+
+        let reroll_rule = starts.first().and_then(|(d, _)| d.reroll_rule.get())
+            .filter(|&rule| starts.iter().all(|(d, _)| d.reroll_rule.get() == Some(rule)));
+
+        // In pool mode each kept die counts on its own, including the extra dice of explosions
+        let successes = imp_ref.pool.get().map(|pool| {
+            let hits: i32 = dice.iter().zip(kept_flags(dice))
+                .filter(|&(_, kept)| kept)
+                .map(|(d, _)| d.sign.get() * pool.successes(d.val.get()))
+                .sum();
+            hits + imp_ref.modifier.get()
+        });
+
+        // Dice outside any named group form an unnamed group of their own, carrying the tray modifier
+        let infos = imp_ref.groups.borrow();
+        let groups = if infos.is_empty() {
+            Vec::new()
+        } else {
+            let members = |group: u32| -> Vec<usize> {
+                starts.iter().enumerate()
+                    .filter(|(_, (d, _))| d.group.get() == group)
+                    .map(|(i, _)| i)
+                    .collect()
+            };
+            let ungrouped = members(0);
+            (!ungrouped.is_empty())
+                .then(|| RollGroup { name: None, dice: ungrouped, modifier: imp_ref.modifier.get(), total: 0 })
+                .into_iter()
+                .chain(infos.iter().enumerate().map(|(i, info)| RollGroup {
+                    name: Some(info.name.clone()),
+                    dice: members(i as u32 + 1),
+                    modifier: info.modifier,
+                    total: 0,
+                }))
+                .collect()
+        };
+
+        // A seed is only recorded when every die, extra dice of explosions included, was drawn from it
+        let seed = dice.first()
+            .and_then(|d| d.sequence.get())
+            .map(|(seed, _)| seed)
+            .filter(|&seed| dice.iter().all(|d| d.sequence.get().is_some_and(|(s, _)| s == seed)));
+        // The draw of each die followed by those of its explosion, deepest last
+        let sequence = match seed {
+            Some(_) => starts.iter()
+                .map(|(d, _)| {
+                    let mut draws: Vec<(u32, u64)> = match d.chain.get() {
+                        Some((chain, _)) => dice.iter()
+                            .filter_map(|e| match (e.chain.get(), e.sequence.get()) {
+                                (Some((c, depth)), Some((_, index))) if c == chain => Some((depth, index)),
+                                _ => None,
+                            })
+                            .collect(),
+                        None => d.sequence.get().map(|(_, index)| (0, index)).into_iter().collect(),
+                    };
+                    draws.sort();
+                    draws.into_iter().map(|(_, index)| index).collect()
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        RollSnapshot {
+            dice: starts.iter().map(|(d, _)| (d.kind, d.val.get())).collect(),
+            kept: starts.iter().map(|&(_, kept)| kept).collect(),
+            keep,
+            advantage: starts.iter().map(|(d, _)| d.advantage.get()).collect(),
+            negative: starts.iter().map(|(d, _)| d.sign.get() < 0).collect(),
+            crits: starts.iter().map(|&(d, kept)| d.crit().filter(|_| kept)).collect(),
+            crit_extra: starts.iter().map(|(d, _)| d.crit_extra.get()).collect(),
+            crit_rule: imp_ref.crit_rule.get(),
+            rerolls: starts.iter().map(|(d, _)| d.rerolled.borrow().clone()).collect(),
+            reroll_rule,
+            chains: starts.iter().map(|(d, _)| d.chain.get().map(|(chain, _)| {
+                let mut extras: Vec<(u32, u32)> = dice.iter()
+                    .filter_map(|e| match e.chain.get() {
+                        Some((c, depth)) if c == chain && depth > 0 => Some((depth, e.val.get())),
+                        _ => None,
+                    })
+                    .collect();
+                extras.sort();
+                extras.into_iter().map(|(_, val)| val).collect()
+            })).collect(),
+            modifier: imp_ref.modifier.get(),
+            expression: imp_ref.expression.borrow().clone(),
+            pool: imp_ref.pool.get(),
+            target: imp_ref.target.get(),
+            interpreter: imp_ref.interpreter.borrow().clone(),
+            successes,
+            groups,
+            repeats: imp_ref.repeats.get().max(1),
+            abilities: Vec::new(),
+            seed,
+            sequence,
+        }
     }
 
-    pub fn restore_roll(&self, dice: &[(DieKind, u32)]) {
+    pub fn restore_roll(&self, entry: &RollEntry) {
+        // Entries from before the tray refused rolls that don't fit fall back to their dice
+        if let Some(roll) = entry.expression.as_deref().and_then(|e| notation::parse(e).ok()) {
+            if self.roll_expression(&roll, entry.repeats).is_ok() {
+                return;
+            }
+        }
+
         let imp = self.imp();
         let mut binding = imp.renderer.borrow_mut();
         if let Some(renderer) = binding.as_mut() {
             renderer.dice.clear();
-            for &(kind, _) in dice {
+            let mut pairs = 0;
+            for (i, &(kind, _)) in entry.dice.iter().enumerate() {
                 if renderer.dice.len() >= imp::MAX_DICE { break; }
-                renderer.dice.push(Die::new(kind));
+                let die = Die::new(kind);
+                match entry.advantage.get(i).copied().flatten() {
+                    Some(advantage) => {
+                        // Consecutive advantage dice form a pair with its own keep set
+                        die.keep.set(Some((TRAY_KEEP_SET + 1 + pairs / 2, advantage.keep())));
+                        die.advantage.set(Some(advantage));
+                        pairs += 1;
+                    }
+                    None => die.keep.set(entry.keep.map(|keep| (TRAY_KEEP_SET, keep))),
+                }
+                die.set_reroll_rule(entry.reroll_rule);
+                if entry.negative.get(i).copied().unwrap_or(false) {
+                    die.sign.set(-1);
+                }
+                if entry.crit_extra.get(i).copied().unwrap_or(false) {
+                    die.crit_extra.set(true);
+                    if entry.crit_rule == Some(CritRule::MaxPlusRoll) {
+                        die.val.set(kind.max_face());
+                        die.pending_rerolls.borrow_mut().clear();
+                        die.tallied.set(true);
+                    }
+                }
+                if entry.chains.get(i).is_some_and(Option::is_some) {
+                    die.explode.set(true);
+                    die.chain.set(Some((i as u32, 0)));
+                }
+                renderer.dice.push(die);
+            }
+
+            // The unnamed group holds the tray's own dice, which carry the entry modifier
+            let mut groups = Vec::new();
+            for group in entry.groups.iter().filter(|g| g.name.is_some()) {
+                groups.push(GroupInfo { name: group.name.clone().unwrap_or_default(), modifier: group.modifier });
+                for &i in &group.dice {
+                    if let Some(die) = renderer.dice.get(i) {
+                        die.group.set(groups.len() as u32);
+                    }
+                }
             }
+            imp.groups.replace(groups);
+            imp.repeats.set(1);
+            imp.crit_rule.set(entry.crit_rule);
+            imp.expression.replace(None);
+            imp.modifier.set(entry.modifier);
         }
     }
 
@@ -1,26 +1,480 @@
 use rand::prelude::*;
 use rand::{rngs::{StdRng, SmallRng}, SeedableRng};
 use gtk::{gio, prelude::*};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::ops::RangeInclusive;
 use std::time::Instant;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 
-fn make_rng() -> Box<dyn RngCore> {
-    let settings = gio::Settings::new("org.lesslie.dice");
-    match settings.string("rng-algorithm").as_str() {
+use crate::custom_dice;
+
+thread_local! {
+    // Read on every roll, so it is only opened once
+    static SETTINGS: gio::Settings = gio::Settings::new("org.lesslie.dice");
+    // The seed seeded rolls are drawing from and the index of its next draw
+    static SEQUENCE: Cell<Option<(u64, u64)>> = const { Cell::new(None) };
+}
+
+// The rng-algorithm picked in Preferences
+pub fn rng_algorithm() -> String {
+    SETTINGS.with(|settings| settings.string("rng-algorithm").into())
+}
+
+// A generator for an rng-algorithm name, for threads that can't read the settings
+pub fn rng_named(algorithm: &str) -> Box<dyn RngCore> {
+    match algorithm {
         "stdrng" => Box::new(StdRng::from_entropy()),
         "smallrng" => Box::new(SmallRng::from_entropy()),
         _ => Box::new(thread_rng()),
     }
 }
 
+// Where seeded rolls are up to as (seed, index of the next draw), or None when
+// rolls aren't seeded. The sequence starts over when the seed changes.
+pub fn sequence() -> Option<(u64, u64)> {
+    let seed = SETTINGS.with(|settings| {
+        settings.boolean("seeded-rolls").then(|| settings.uint64("roll-seed"))
+    })?;
+    SEQUENCE.with(|sequence| match sequence.get() {
+        Some((current, index)) if current == seed => Some((seed, index)),
+        _ => {
+            sequence.set(Some((seed, 0)));
+            Some((seed, 0))
+        }
+    })
+}
+
+// Moves seeded rolls to draw `index` of the current seed next
+pub fn seek(index: u64) {
+    if let Some((seed, _)) = sequence() {
+        SEQUENCE.with(|sequence| sequence.set(Some((seed, index))));
+    }
+}
+
+// Every draw of a seeded sequence gets a generator of its own, so any one of
+// them can be worked out from the seed and its index alone. They always use
+// StdRng, whichever algorithm is picked, so a seed means the same rolls everywhere.
+fn seeded_rng(seed: u64, index: u64) -> StdRng {
+    StdRng::seed_from_u64(seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
+// The generator for one roll of a die, with the draw it took when rolls are seeded.
+// A roll takes a single draw, whose generator gives the face first, then the spin,
+// then any faces the reroll rule asks for.
+fn next_roll() -> (Option<(u64, u64)>, Box<dyn RngCore>) {
+    match sequence() {
+        Some((seed, index)) => {
+            seek(index + 1);
+            (Some((seed, index)), Box::new(seeded_rng(seed, index)))
+        }
+        None => (None, rng_named(&rng_algorithm())),
+    }
+}
+
+// How every die picks the face it lands on
+pub fn roll_face(rng: &mut dyn RngCore, kind: DieKind) -> u32 {
+    rng.gen_range(kind.faces())
+}
+
+fn spin_seed(rng: &mut dyn RngCore) -> [u32; 3] {
+    [
+        rng.gen_range(2..=5),
+        rng.gen_range(2..=5),
+        rng.gen_range(2..=5),
+    ]
+}
+
+// Faces at or above `success` are critical successes and faces at or below `failure`
+// critical failures; 0 turns either off
+#[derive(Clone, Copy, PartialEq, Default)]
+pub struct CritRange {
+    pub success: u32,
+    pub failure: u32,
+}
+
+pub fn crit_ranges() -> BTreeMap<String, (u32, u32)> {
+    let settings = gio::Settings::new("org.lesslie.dice");
+    settings.value("crit-ranges").get().unwrap_or_default()
+}
+
+pub fn set_crit_range(kind: DieKind, range: CritRange) {
+    let Some(key) = kind.crit_key() else { return };
+    let mut ranges = crit_ranges();
+    ranges.insert(key.to_string(), (range.success, range.failure));
+    let settings = gio::Settings::new("org.lesslie.dice");
+    settings.set_value("crit-ranges", &ranges.to_variant()).ok();
+}
+
 #[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum DieKind {
     Four,
     Six,
+    // Fate/Fudge die: a d6 with two each of -1, 0 and +1
+    Fudge,
     Eight,
     Ten,
     Twelve,
-    Twenty
+    Twenty,
+    Hundred,
+    // Any other side count, drawn as a labelled token
+    Custom(u32),
+    // A user-defined die, by the id of its definition
+    Defined(u32),
+}
+
+pub const MAX_SIDES: u32 = 1000;
+
+// Dice that can be given a critical range in Preferences
+pub const CRIT_KINDS: [DieKind; 7] = [
+    DieKind::Four,
+    DieKind::Six,
+    DieKind::Eight,
+    DieKind::Ten,
+    DieKind::Twelve,
+    DieKind::Twenty,
+    DieKind::Hundred,
+];
+
+impl DieKind {
+    // Name of the die in the crit-ranges setting
+    pub fn crit_key(self) -> Option<&'static str> {
+        match self {
+            DieKind::Four => Some("d4"),
+            DieKind::Six => Some("d6"),
+            DieKind::Eight => Some("d8"),
+            DieKind::Ten => Some("d10"),
+            DieKind::Twelve => Some("d12"),
+            DieKind::Twenty => Some("d20"),
+            DieKind::Hundred => Some("d100"),
+            DieKind::Fudge | DieKind::Custom(_) | DieKind::Defined(_) => None,
+        }
+    }
+
+    // The face with the highest value
+    pub fn max_face(self) -> u32 {
+        match self {
+            DieKind::Defined(id) => custom_dice::with(id, |def| {
+                def.faces.iter()
+                    .enumerate()
+                    .max_by_key(|(_, face)| face.value)
+                    .map(|(i, _)| i as u32 + 1)
+            })
+            .flatten()
+            .unwrap_or(self.sides()),
+            _ => self.sides(),
+        }
+    }
+
+    pub fn crit_range(self) -> CritRange {
+        self.crit_key()
+            .and_then(|key| crit_ranges().get(key).copied())
+            .map(|(success, failure)| CritRange { success, failure })
+            .unwrap_or_default()
+    }
+
+    pub fn sides(self) -> u32 {
+        match self {
+            DieKind::Four => 4,
+            DieKind::Six | DieKind::Fudge => 6,
+            DieKind::Eight => 8,
+            DieKind::Ten => 10,
+            DieKind::Twelve => 12,
+            DieKind::Twenty => 20,
+            DieKind::Hundred => 100,
+            DieKind::Custom(sides) => sides,
+            DieKind::Defined(id) => custom_dice::with(id, |def| def.faces.len() as u32).unwrap_or(6),
+        }
+    }
+
+    // How the die is written, e.g. "d20", or the name of a user-defined one
+    pub fn name(self) -> String {
+        match self {
+            DieKind::Fudge => "dF".to_string(),
+            DieKind::Defined(id) => custom_dice::with(id, |def| def.name.clone()).unwrap_or_else(|| "Custom Die".to_string()),
+            _ => format!("d{}", self.sides()),
+        }
+    }
+
+    // The faces a roll can land on
+    pub fn faces(self) -> RangeInclusive<u32> {
+        match self {
+            DieKind::Four => 1..=4,
+            DieKind::Six | DieKind::Fudge => 1..=6,
+            DieKind::Eight => 1..=8,
+            DieKind::Ten => 1..=10,
+            DieKind::Twelve => 1..=12,
+            DieKind::Twenty => 1..=20,
+            DieKind::Hundred => 1..=100,
+            DieKind::Custom(sides) => 1..=sides.max(1),
+            DieKind::Defined(_) => 1..=self.sides().max(1),
+        }
+    }
+
+    // The kind whose mesh is drawn for this one
+    pub fn mesh(self) -> DieKind {
+        match self {
+            DieKind::Defined(id) => custom_dice::with(id, |def| def.mesh).unwrap_or(DieKind::Six),
+            kind => kind,
+        }
+    }
+
+    pub fn from_sides(sides: u32) -> Option<Self> {
+        match sides {
+            4 => Some(DieKind::Four),
+            6 => Some(DieKind::Six),
+            8 => Some(DieKind::Eight),
+            10 => Some(DieKind::Ten),
+            12 => Some(DieKind::Twelve),
+            20 => Some(DieKind::Twenty),
+            100 => Some(DieKind::Hundred),
+            2..=MAX_SIDES => Some(DieKind::Custom(sides)),
+            _ => None,
+        }
+    }
+
+    // What a face counts for; faces are numbered 1 to sides() and run from low to high
+    pub fn value(self, face: u32) -> i32 {
+        match self {
+            DieKind::Fudge => (face as i32 - 1) / 2 - 1,
+            DieKind::Defined(id) => custom_dice::with(id, |def| def.face(face).map_or(0, |f| f.value)).unwrap_or(0),
+            _ => face as i32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Keep {
+    Highest(u32),
+    Lowest(u32),
+    DropHighest(u32),
+    DropLowest(u32),
+}
+
+impl Keep {
+    // Returns which of `vals` count towards the total, in their original order.
+    pub fn apply(self, vals: &[u32]) -> Vec<bool> {
+        let n = vals.len();
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by_key(|&i| vals[i]);
+
+        let dropped = match self {
+            Keep::Highest(k) => &order[..n.saturating_sub(k as usize)],
+            Keep::Lowest(k) => &order[(k as usize).min(n)..],
+            Keep::DropHighest(k) => &order[n - (k as usize).min(n)..],
+            Keep::DropLowest(k) => &order[..(k as usize).min(n)],
+        };
+
+        let mut kept = vec![true; n];
+        for &i in dropped {
+            kept[i] = false;
+        }
+        kept
+    }
+}
+
+impl fmt::Display for Keep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Keep::Highest(n) => write!(f, "kh{}", n),
+            Keep::Lowest(n) => write!(f, "kl{}", n),
+            Keep::DropHighest(n) => write!(f, "dh{}", n),
+            Keep::DropLowest(n) => write!(f, "dl{}", n),
+        }
+    }
+}
+
+// A die that landed in its critical range
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Crit {
+    Success,
+    Failure,
+}
+
+impl CritRange {
+    pub fn crit(self, val: u32) -> Option<Crit> {
+        if self.success > 0 && val >= self.success {
+            Some(Crit::Success)
+        } else if self.failure > 0 && val <= self.failure {
+            Some(Crit::Failure)
+        } else {
+            None
+        }
+    }
+}
+
+// How damage is raised on a critical hit
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum CritRule {
+    // Every damage die is rolled twice
+    DoubleDice,
+    // Every damage die gets a second one showing its highest face
+    MaxPlusRoll,
+    // The total, modifier included, counts twice
+    DoubleTotal,
+}
+
+impl CritRule {
+    pub fn label(self) -> &'static str {
+        match self {
+            CritRule::DoubleDice => "Double Dice",
+            CritRule::MaxPlusRoll => "Max + Roll",
+            CritRule::DoubleTotal => "Double Total",
+        }
+    }
+
+    // Name used as the target of the crit-damage action
+    pub fn name(self) -> &'static str {
+        match self {
+            CritRule::DoubleDice => "double-dice",
+            CritRule::MaxPlusRoll => "max-plus-roll",
+            CritRule::DoubleTotal => "double-total",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [CritRule::DoubleDice, CritRule::MaxPlusRoll, CritRule::DoubleTotal]
+            .into_iter()
+            .find(|rule| rule.name() == name)
+    }
+}
+
+// Dice showing at or below the threshold are rolled again
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Reroll {
+    Once(u32),
+    Until(u32),
+}
+
+// Every reroll is animated, so long streaks are cut short
+const MAX_REROLLS: usize = 10;
+
+impl Reroll {
+    pub fn threshold(self) -> u32 {
+        match self {
+            Reroll::Once(n) | Reroll::Until(n) => n,
+        }
+    }
+
+    // The rolls that replace `first` on a die with `sides` faces under the rule, in order
+    pub fn follow_ups(self, sides: u32, first: u32, mut roll: impl FnMut() -> u32) -> VecDeque<u32> {
+        let limit = match self {
+            Reroll::Once(_) => 1,
+            // No face is above the threshold, so rerolling could never stop
+            Reroll::Until(n) if n >= sides => 0,
+            Reroll::Until(_) => MAX_REROLLS,
+        };
+        let mut rolls = VecDeque::new();
+        let mut val = first;
+        while val <= self.threshold() && rolls.len() < limit {
+            val = roll();
+            rolls.push_back(val);
+        }
+        rolls
+    }
+
+    // The chance of each face being the one a die finally shows under the rule,
+    // with the same limit on rerolls as follow_ups()
+    pub fn face_odds(self, kind: DieKind) -> Vec<f64> {
+        let sides = kind.faces().count();
+        let p = 1.0 / sides as f64;
+        let low = self.threshold().min(sides as u32) as f64 * p;
+        let low_odds = match self {
+            Reroll::Once(_) => p * low,
+            Reroll::Until(n) if n as usize >= sides => p,
+            Reroll::Until(_) => p * low.powi(MAX_REROLLS as i32),
+        };
+        let high_odds = match self {
+            Reroll::Once(_) => p + p * low,
+            Reroll::Until(_) => p * (0..=MAX_REROLLS as i32).map(|k| low.powi(k)).sum::<f64>(),
+        };
+        kind.faces()
+            .map(|face| if face <= self.threshold() { low_odds } else { high_odds })
+            .collect()
+    }
+}
+
+impl fmt::Display for Reroll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reroll::Once(n) => write!(f, "ro{}", n),
+            Reroll::Until(n) => write!(f, "r{}", n),
+        }
+    }
+}
+
+// D&D style d20 rolls: two dice, keeping the better or worse
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Advantage {
+    Advantage,
+    Disadvantage,
+}
+
+impl Advantage {
+    pub fn keep(self) -> Keep {
+        match self {
+            Advantage::Advantage => Keep::Highest(1),
+            Advantage::Disadvantage => Keep::Lowest(1),
+        }
+    }
+
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            Advantage::Advantage => "adv",
+            Advantage::Disadvantage => "dis",
+        }
+    }
+}
+
+// Counts dice meeting a target number instead of summing them
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Pool {
+    pub target: u32,
+    pub ones_subtract: bool,
+    pub tens_double: bool,
+}
+
+impl Pool {
+    // The number of successes a single die adds, negative when a 1 takes one away
+    pub fn successes(self, val: u32) -> i32 {
+        if val >= self.target {
+            if self.tens_double && val == 10 { 2 } else { 1 }
+        } else if self.ones_subtract && val == 1 {
+            -1
+        } else {
+            0
+        }
+    }
+}
+
+// A number the roll's total is compared against, such as a DC or AC
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Target {
+    pub value: i32,
+    // Percentile systems pass at or under the target instead of at or over it
+    #[serde(default)]
+    pub roll_under: bool,
+}
+
+impl Target {
+    // How far the total beat the target by, negative when it fell short
+    pub fn margin(self, total: i32) -> i32 {
+        if self.roll_under { self.value - total } else { total - self.value }
+    }
+
+    pub fn passes(self, total: i32) -> bool {
+        self.margin(total) >= 0
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.roll_under {
+            write!(f, "≤ {}", self.value)
+        } else {
+            write!(f, "DC {}", self.value)
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -32,17 +486,40 @@ pub struct Die {
   pub prev_pos: Cell<Option<(f32, f32)>>,
   pub reflow_from: Cell<Option<(f32, f32)>>,
   pub reflow_start: Cell<Option<Instant>>,
+  // Dice sharing a set id are kept or dropped together under the rule
+  pub keep: Cell<Option<(u32, Keep)>>,
+  // Set on both dice of an advantage or disadvantage pair
+  pub advantage: Cell<Option<Advantage>>,
+  // Exploding dice roll again on their highest face
+  pub explode: Cell<bool>,
+  // Chain id and depth; the extra dice of an explosion share the id of the die that started it
+  pub chain: Cell<Option<(u32, u32)>>,
+  pub reroll_rule: Cell<Option<Reroll>>,
+  // Faces still to be shown after the current one under the reroll rule
+  pub pending_rerolls: RefCell<VecDeque<u32>>,
+  // Values replaced by rerolling just this die, oldest first
+  pub rerolled: RefCell<Vec<u32>>,
+  pub selected: Cell<bool>,
+  // Named group the die was rolled in, 0 when it belongs to none
+  pub group: Cell<u32>,
+  // Read from the settings whenever the die is rolled
+  pub crit_range: Cell<CritRange>,
+  // Added to the tray by a crit damage rule
+  pub crit_extra: Cell<bool>,
+  // Set once the face it settled on has been counted in the statistics, or
+  // when the face was placed rather than rolled
+  pub tallied: Cell<bool>,
+  // -1 for dice taken away from the total, as the d4 in 1d20 - 1d4
+  pub sign: Cell<i32>,
+  // Seed and index of the draw behind the current face and its rerolls, when rolls are seeded
+  pub sequence: Cell<Option<(u64, u64)>>,
 }
 
 impl Die {
     pub fn new(kind: DieKind) -> Self {
-        let mut rng = make_rng();
-        let val = Self::generate_roll(kind);
-        let spin_seed = [
-            rng.gen_range(2..=5),
-            rng.gen_range(2..=5),
-            rng.gen_range(2..=5),
-        ];
+        let (sequence, mut rng) = next_roll();
+        let val = roll_face(&mut *rng, kind);
+        let spin_seed = spin_seed(&mut *rng);
         Self {
             time: Cell::new(Some(Instant::now())),
             kind,
@@ -51,32 +528,206 @@ impl Die {
             prev_pos: Cell::new(None),
             reflow_from: Cell::new(None),
             reflow_start: Cell::new(None),
+            keep: Cell::new(None),
+            advantage: Cell::new(None),
+            explode: Cell::new(false),
+            chain: Cell::new(None),
+            reroll_rule: Cell::new(None),
+            pending_rerolls: RefCell::new(VecDeque::new()),
+            rerolled: RefCell::new(Vec::new()),
+            selected: Cell::new(false),
+            group: Cell::new(0),
+            crit_range: Cell::new(kind.crit_range()),
+            crit_extra: Cell::new(false),
+            tallied: Cell::new(false),
+            sign: Cell::new(1),
+            sequence: Cell::new(sequence),
         }
     }
 
     pub fn roll(&self) {
-        let mut rng = make_rng();
+        let (sequence, mut rng) = next_roll();
+        self.sequence.set(sequence);
+        self.val.set(roll_face(&mut *rng, self.kind));
+        self.crit_range.set(self.kind.crit_range());
+        self.spin(&mut *rng);
+        self.queue_rerolls();
+    }
+
+    fn spin(&self, rng: &mut dyn RngCore) {
         self.time.set(Some(Instant::now()));
-        self.val.set(Self::generate_roll(self.kind));
-        self.spin_seed.set([
-            rng.gen_range(2..=5),
-            rng.gen_range(2..=5),
-            rng.gen_range(2..=5),
-        ]);
+        self.tallied.set(false);
+        self.spin_seed.set(spin_seed(rng));
+    }
+
+    // Applies the rule to the current face as well as every later roll
+    pub fn set_reroll_rule(&self, rule: Option<Reroll>) {
+        self.reroll_rule.set(rule);
+        self.queue_rerolls();
     }
 
-    fn generate_roll(kind: DieKind) -> u32 {
-        let mut rng = make_rng();
+    fn queue_rerolls(&self) {
+        let rolls = self.reroll_rule.get()
+            .map(|rule| {
+                let mut rng = self.follow_up_rng();
+                rule.follow_ups(self.kind.sides(), self.val.get(), || roll_face(&mut *rng, self.kind))
+            })
+            .unwrap_or_default();
+        self.pending_rerolls.replace(rolls);
+    }
 
-        match kind {
-            DieKind::Four => rng.gen_range(1..=4),
-            DieKind::Six => rng.gen_range(1..=6),
-            DieKind::Eight => rng.gen_range(1..=8),
-            DieKind::Ten => rng.gen_range(1..=10),
-            DieKind::Twelve => rng.gen_range(1..=12),
-            DieKind::Twenty => rng.gen_range(1..=20),
+    // Where the reroll rule draws from; a seeded die carries on past its face and spin
+    fn follow_up_rng(&self) -> Box<dyn RngCore> {
+        match self.sequence.get() {
+            Some((seed, index)) => {
+                let mut rng = seeded_rng(seed, index);
+                roll_face(&mut rng, self.kind);
+                spin_seed(&mut rng);
+                Box::new(rng)
+            }
+            None => rng_named(&rng_algorithm()),
+        }
+    }
+
+    // Shows the next face the reroll rule produced, spinning the die again
+    pub fn advance_reroll(&self) {
+        let Some(next) = self.pending_rerolls.borrow_mut().pop_front() else { return };
+        self.rerolled.borrow_mut().push(self.val.get());
+        self.val.set(next);
+        // Only the animation is drawn again, so it needn't come from the seeded sequence
+        self.spin(&mut thread_rng());
+    }
+
+    // Rolls again, remembering the value it replaces
+    pub fn reroll(&self) {
+        self.rerolled.borrow_mut().push(self.val.get());
+        self.roll();
+    }
+
+    // Whether this die was spawned by another one exploding
+    pub fn is_explosion(&self) -> bool {
+        self.chain.get().is_some_and(|(_, depth)| depth > 0)
+    }
+
+    pub fn value(&self) -> i32 {
+        self.kind.value(self.val.get())
+    }
+
+    // What the die adds to the total
+    pub fn signed_value(&self) -> i32 {
+        self.sign.get() * self.value()
+    }
+
+    pub fn should_explode(&self) -> bool {
+        self.explode.get() && self.val.get() == self.kind.max_face()
+    }
+
+    pub fn crit(&self) -> Option<Crit> {
+        self.crit_range.get().crit(self.val.get())
+    }
+
+    // The text shown on each mesh of the die once it settles
+    pub fn face_labels(&self) -> Vec<String> {
+        let val = self.val.get();
+        match self.kind {
+            DieKind::Hundred => vec![
+                format!("{:02}", (val / 10) % 10 * 10),
+                (val % 10).to_string(),
+            ],
+            DieKind::Fudge => vec![match self.value() {
+                1 => "+".to_string(),
+                -1 => "−".to_string(),
+                _ => String::new(),
+            }],
+            DieKind::Defined(id) => vec![
+                custom_dice::with(id, |def| def.face(val).map(|f| f.label.clone()))
+                    .flatten()
+                    .unwrap_or_default(),
+            ],
+            _ => vec![val.to_string()],
         }
     }
 }
 
+// The value of a die together with any dice its explosion spawned
+pub fn chain_value(dice: &[Die], die: &Die) -> u32 {
+    match die.chain.get() {
+        Some((chain, _)) => dice.iter()
+            .filter(|d| d.chain.get().is_some_and(|(c, _)| c == chain))
+            .map(|d| d.val.get())
+            .sum(),
+        None => die.val.get(),
+    }
+}
 
+// Returns whether each die counts towards the total under its keep rule
+pub fn kept_flags(dice: &[Die]) -> Vec<bool> {
+    let mut sets: BTreeMap<u32, (Keep, Vec<usize>)> = BTreeMap::new();
+    for (i, die) in dice.iter().enumerate() {
+        if let (Some((set, keep)), false) = (die.keep.get(), die.is_explosion()) {
+            sets.entry(set).or_insert((keep, Vec::new())).1.push(i);
+        }
+    }
+
+    let mut kept = vec![true; dice.len()];
+    for (keep, members) in sets.values() {
+        let vals: Vec<u32> = members.iter().map(|&i| chain_value(dice, &dice[i])).collect();
+        for (&i, k) in members.iter().zip(keep.apply(&vals)) {
+            kept[i] = k;
+        }
+    }
+
+    // Extra dice from an explosion count whenever the die that started it does
+    for (i, die) in dice.iter().enumerate() {
+        if let (Some((chain, _)), true) = (die.chain.get(), die.is_explosion()) {
+            if let Some(start) = dice.iter().position(|d| d.chain.get() == Some((chain, 0))) {
+                kept[i] = kept[start];
+            }
+        }
+    }
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hands out `faces` in turn, then keeps showing the last one
+    fn rolls(faces: &[u32]) -> impl FnMut() -> u32 + '_ {
+        let mut next = 0;
+        move || {
+            let face = faces[next.min(faces.len() - 1)];
+            next += 1;
+            face
+        }
+    }
+
+    #[test]
+    fn reroll_once_takes_the_second_roll() {
+        assert_eq!(Reroll::Once(2).follow_ups(6, 2, rolls(&[1, 5])), [1]);
+        assert_eq!(Reroll::Once(2).follow_ups(6, 3, rolls(&[1])).len(), 0);
+    }
+
+    #[test]
+    fn reroll_until_stops_above_the_threshold() {
+        assert_eq!(Reroll::Until(2).follow_ups(6, 1, rolls(&[2, 1, 4, 1])), [2, 1, 4]);
+        assert_eq!(Reroll::Until(2).follow_ups(6, 6, rolls(&[1])).len(), 0);
+        // A streak of low faces is cut short, and a rule no face can pass never rerolls
+        assert_eq!(Reroll::Until(1).follow_ups(6, 1, rolls(&[1])).len(), MAX_REROLLS);
+        assert_eq!(Reroll::Until(6).follow_ups(6, 1, rolls(&[1])).len(), 0);
+    }
+
+    #[test]
+    fn reroll_odds_add_up() {
+        let odds = Reroll::Once(1).face_odds(DieKind::Six);
+        assert!((odds[0] - 1.0 / 36.0).abs() < 1e-12);
+        assert!(odds[1..].iter().all(|p| (p - 7.0 / 36.0).abs() < 1e-12));
+
+        for rule in [Reroll::Once(3), Reroll::Until(1), Reroll::Until(5), Reroll::Until(6)] {
+            let odds = rule.face_odds(DieKind::Six);
+            assert!((odds.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        }
+        // A rule no face can pass leaves the die as it is
+        assert!(Reroll::Until(6).face_odds(DieKind::Six).iter().all(|p| (p - 1.0 / 6.0).abs() < 1e-12));
+    }
+}
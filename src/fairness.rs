@@ -0,0 +1,131 @@
+// Checks that the selected generator gives every face of every die a fair
+// chance. Each die is rolled many times the way the tray rolls it, then the
+// counts go through a chi-square test and the order through a runs test.
+
+use crate::die::{self, DieKind, CRIT_KINDS};
+
+pub const SAMPLES: u64 = 200_000;
+
+// A test fails when a fair die would do this badly less than once in this many
+pub const ALPHA: f64 = 0.001;
+
+pub struct Report {
+    pub kind: DieKind,
+    // How often each face came up, from face 1
+    pub counts: Vec<u64>,
+    pub chi_square: f64,
+    pub chi_square_p: f64,
+    // Runs of rolls in a row above or below the middle face
+    pub runs: u64,
+    pub expected_runs: f64,
+    pub runs_p: f64,
+}
+
+impl Report {
+    pub fn degrees_of_freedom(&self) -> usize {
+        self.counts.len() - 1
+    }
+
+    pub fn chi_square_passed(&self) -> bool {
+        self.chi_square_p >= ALPHA
+    }
+
+    pub fn runs_passed(&self) -> bool {
+        self.runs_p >= ALPHA
+    }
+
+    pub fn passed(&self) -> bool {
+        self.chi_square_passed() && self.runs_passed()
+    }
+}
+
+// Tests every polyhedral die with the rng-algorithm named; slow enough to want a thread of its own
+pub fn run(algorithm: &str) -> Vec<Report> {
+    let mut rng = die::rng_named(algorithm);
+    CRIT_KINDS.iter()
+        .map(|&kind| {
+            let sides = kind.faces().count();
+            let middle = (sides as f64 + 1.0) / 2.0;
+            let mut counts = vec![0u64; sides];
+            let (mut above, mut below, mut runs) = (0u64, 0u64, 0u64);
+            let mut last_above = None;
+            for _ in 0..SAMPLES {
+                let face = die::roll_face(&mut *rng, kind);
+                counts[face as usize - 1] += 1;
+                let is_above = face as f64 > middle;
+                if is_above { above += 1 } else { below += 1 }
+                if last_above != Some(is_above) {
+                    runs += 1;
+                }
+                last_above = Some(is_above);
+            }
+
+            let expected = SAMPLES as f64 / sides as f64;
+            let chi_square: f64 = counts.iter().map(|&count| (count as f64 - expected).powi(2) / expected).sum();
+            let chi_square_p = chi_square_upper_tail(chi_square, sides - 1);
+
+            // Wald–Wolfowitz: the runs a random order of `above` and `below` rolls would have
+            let n = (above + below) as f64;
+            let expected_runs = 2.0 * above as f64 * below as f64 / n + 1.0;
+            let variance = (expected_runs - 1.0) * (expected_runs - 2.0) / (n - 1.0);
+            let runs_p = if variance > 0.0 {
+                erfc((runs as f64 - expected_runs).abs() / variance.sqrt() / std::f64::consts::SQRT_2)
+            } else {
+                0.0
+            };
+
+            Report { kind, counts, chi_square, chi_square_p, runs, expected_runs, runs_p }
+        })
+        .collect()
+}
+
+// The chance of a chi-square statistic at least `x` with `k` degrees of freedom,
+// by the Wilson–Hilferty approximation
+fn chi_square_upper_tail(x: f64, k: usize) -> f64 {
+    let k = k as f64;
+    let spread = 2.0 / (9.0 * k);
+    let z = ((x / k).cbrt() - (1.0 - spread)) / spread.sqrt();
+    erfc(z / std::f64::consts::SQRT_2) / 2.0
+}
+
+// Complementary error function, to within 1.2e-7 (Numerical Recipes' erfcc)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.26551223
+        + t * (1.00002368
+        + t * (0.37409196
+        + t * (0.09678418
+        + t * (-0.18628806
+        + t * (0.27886807
+        + t * (-1.13520398
+        + t * (1.48851587
+        + t * (-0.82215223
+        + t * 0.17087277)))))))))
+        .exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erfc_matches_tables() {
+        assert!((erfc(0.0) - 1.0).abs() < 1.2e-7);
+        assert!((erfc(0.5) - 0.479_500_122).abs() < 1.2e-7);
+        assert!((erfc(1.0) - 0.157_299_207).abs() < 1.2e-7);
+        assert!((erfc(2.0) - 0.004_677_735).abs() < 1.2e-7);
+        assert!((erfc(-1.0) - 1.842_700_793).abs() < 1.2e-7);
+    }
+
+    #[test]
+    fn upper_tail_matches_critical_values() {
+        // 95th percentiles of the chi-square distribution for a d6 and a d20
+        assert!((chi_square_upper_tail(11.070, 5) - 0.05).abs() < 0.002);
+        assert!((chi_square_upper_tail(30.144, 19) - 0.05).abs() < 0.002);
+        assert!((chi_square_upper_tail(4.351, 5) - 0.5).abs() < 0.005);
+        assert!(chi_square_upper_tail(0.0, 5) > 0.99);
+        assert!(chi_square_upper_tail(100.0, 5) < 1e-6);
+    }
+}
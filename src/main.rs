@@ -18,14 +18,24 @@
  * SPDX-License-Identifier: GPL-3.0-or-later
  */
 
+mod ability_scores;
+mod analysis;
 mod application;
 mod config;
+mod custom_dice;
+mod dashboard;
 mod window;
 mod dice_area;
 mod die;
+mod fairness;
+mod notation;
+mod outcomes;
 mod preferences;
+mod probability;
 mod roll_history;
 mod sidebar;
+mod simulation;
+mod statistics;
 
 use self::application::DiceApplication;
 use self::window::DiceWindow;
@@ -0,0 +1,482 @@
+// Standard dice notation, e.g. "4d6kh3 + 1d4 - 2" or "To hit: 1d20+5; Damage: 2d6+3".
+//
+//   roll    := group (';' group)*
+//   group   := [name ':'] expr
+//   expr    := ['+' | '-'] operand (('+' | '-') operand)*
+//   operand := number | [number] 'd' (number | '%') ['!'] [reroll] [keep]
+//            | [number] 'd' 'f' [keep]
+//   reroll  := ('ro' | 'r') [number]
+//   keep    := ('kh' | 'kl' | 'dh' | 'dl' | 'k') [number]
+//
+// A '!' makes dice explode, rolling again on their highest face. 'ro' rerolls
+// dice at or below the number once, 'r' until they land above it.
+// Whitespace is ignored between tokens and letters are case-insensitive.
+
+use std::fmt;
+
+use crate::die::{Die, DieKind, Keep, Reroll, MAX_SIDES};
+
+const MAX_COUNT: u32 = 100;
+const MAX_CONSTANT: u32 = 1_000_000;
+
+#[derive(Clone, PartialEq)]
+pub enum Operand {
+    Dice { count: u32, kind: DieKind, explode: bool, reroll: Option<Reroll>, keep: Option<Keep> },
+    Constant(u32),
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Term {
+    pub sign: i32,
+    pub operand: Operand,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Expr {
+    pub terms: Vec<Term>,
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            match (i, term.sign < 0) {
+                (0, true) => write!(f, "-")?,
+                (0, false) => {}
+                (_, true) => write!(f, " - ")?,
+                (_, false) => write!(f, " + ")?,
+            }
+            match &term.operand {
+                Operand::Dice { count, kind, explode, reroll, keep } => {
+                    match kind {
+                        DieKind::Fudge => write!(f, "{}dF", count)?,
+                        _ => write!(f, "{}d{}", count, kind.sides())?,
+                    }
+                    if *explode {
+                        write!(f, "!")?;
+                    }
+                    if let Some(reroll) = reroll {
+                        write!(f, "{}", reroll)?;
+                    }
+                    if let Some(keep) = keep {
+                        write!(f, "{}", keep)?;
+                    }
+                }
+                Operand::Constant(n) => write!(f, "{}", n)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Group {
+    pub name: Option<String>,
+    pub expr: Expr,
+}
+
+// One or more groups rolled into the tray together, each with its own subtotal
+#[derive(Clone, PartialEq)]
+pub struct Roll {
+    pub groups: Vec<Group>,
+}
+
+impl Roll {
+    // Dice every group of the roll puts in the tray, before any explode
+    pub fn dice_count(&self) -> u32 {
+        self.groups.iter()
+            .flat_map(|group| &group.expr.terms)
+            .map(|term| match term.operand {
+                Operand::Dice { count, .. } => count,
+                Operand::Constant(_) => 0,
+            })
+            .sum()
+    }
+}
+
+impl fmt::Display for Roll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, group) in self.groups.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            if let Some(name) = &group.name {
+                write!(f, "{}: ", name)?;
+            }
+            write!(f, "{}", group.expr)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ParseError {
+    // Character offset into the input where parsing failed
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (column {})", self.message, self.column + 1)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    // Group names keep the case they were typed in
+    original: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Self {
+        Self {
+            chars: input.chars().map(|c| c.to_ascii_lowercase()).collect(),
+            original: input.chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_next(&self) -> Option<char> {
+        self.chars.get(self.pos + 1).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    fn error_at(&self, column: usize, message: impl Into<String>) -> ParseError {
+        ParseError { column, message: message.into() }
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        self.error_at(self.pos, message)
+    }
+
+    fn number(&mut self) -> Result<Option<u32>, ParseError> {
+        let start = self.pos;
+        let mut value: u32 = 0;
+        while let Some(digit) = self.peek().and_then(|c| c.to_digit(10)) {
+            value = value * 10 + digit;
+            if value > MAX_CONSTANT {
+                return Err(self.error_at(start, "Number is too large"));
+            }
+            self.pos += 1;
+        }
+        Ok((self.pos > start).then_some(value))
+    }
+
+    fn roll(&mut self) -> Result<Roll, ParseError> {
+        let mut groups = vec![self.group()?];
+        while self.peek() == Some(';') {
+            self.pos += 1;
+            groups.push(self.group()?);
+        }
+        Ok(Roll { groups })
+    }
+
+    fn group(&mut self) -> Result<Group, ParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+
+        // A name runs up to a ':' that comes before the end of the group
+        let end = self.chars[start..].iter().position(|&c| c == ';').map_or(self.chars.len(), |i| start + i);
+        let name = match self.chars[start..end].iter().position(|&c| c == ':') {
+            Some(colon) => {
+                let name: String = self.original[start..start + colon].iter().collect();
+                let name = name.trim().to_string();
+                if name.is_empty() {
+                    return Err(self.error_at(start + colon, "Expected a group name"));
+                }
+                self.pos = start + colon + 1;
+                Some(name)
+            }
+            None => None,
+        };
+
+        let expr = self.expr()?;
+        Ok(Group { name, expr })
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut terms = Vec::new();
+
+        self.skip_whitespace();
+        if matches!(self.peek(), None | Some(';')) {
+            return Err(self.error("Expected a roll"));
+        }
+
+        let mut sign = 1;
+        if let Some(c @ ('+' | '-')) = self.peek() {
+            sign = if c == '-' { -1 } else { 1 };
+            self.pos += 1;
+            self.skip_whitespace();
+        }
+
+        loop {
+            let operand = self.operand()?;
+            terms.push(Term { sign, operand });
+
+            self.skip_whitespace();
+            sign = match self.peek() {
+                None | Some(';') => break,
+                Some('+') => 1,
+                Some('-') => -1,
+                Some(c) => return Err(self.error(format!("Unexpected '{}'", c))),
+            };
+            self.pos += 1;
+            self.skip_whitespace();
+        }
+
+        Ok(Expr { terms })
+    }
+
+    fn operand(&mut self) -> Result<Operand, ParseError> {
+        let start = self.pos;
+        let count = self.number()?;
+
+        if self.peek() != Some('d') {
+            return count
+                .map(Operand::Constant)
+                .ok_or_else(|| self.error("Expected a number or die"));
+        }
+
+        let count = count.unwrap_or(1);
+        if count == 0 || count > MAX_COUNT {
+            return Err(self.error_at(start, format!("Dice count must be between 1 and {}", MAX_COUNT)));
+        }
+        self.pos += 1;
+
+        // Fudge dice have no highest or low faces to explode or reroll
+        if self.peek() == Some('f') {
+            self.pos += 1;
+            let keep = self.keep(count)?;
+            return Ok(Operand::Dice { count, kind: DieKind::Fudge, explode: false, reroll: None, keep });
+        }
+
+        let sides_start = self.pos;
+        let sides = if self.peek() == Some('%') {
+            self.pos += 1;
+            100
+        } else {
+            self.number()?.ok_or_else(|| self.error("Expected number of sides"))?
+        };
+        let kind = DieKind::from_sides(sides)
+            .ok_or_else(|| self.error_at(sides_start, format!("Dice need 2 to {} sides", MAX_SIDES)))?;
+
+        let explode = self.peek() == Some('!');
+        if explode {
+            self.pos += 1;
+        }
+
+        let reroll = self.reroll(kind)?;
+        let keep = self.keep(count)?;
+        Ok(Operand::Dice { count, kind, explode, reroll, keep })
+    }
+
+    fn reroll(&mut self, kind: DieKind) -> Result<Option<Reroll>, ParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let reroll: fn(u32) -> Reroll = match (self.peek(), self.peek_next()) {
+            (Some('r'), Some('o')) => {
+                self.pos += 2;
+                Reroll::Once
+            }
+            (Some('r'), _) => {
+                self.pos += 1;
+                Reroll::Until
+            }
+            _ => return Ok(None),
+        };
+        let n = self.number()?.unwrap_or(1);
+        if n == 0 || n >= kind.sides() {
+            return Err(self.error_at(start, format!("Can only reroll 1 to {}", kind.sides() - 1)));
+        }
+        Ok(Some(reroll(n)))
+    }
+
+    fn keep(&mut self, count: u32) -> Result<Option<Keep>, ParseError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let keep: fn(u32) -> Keep = match (self.peek(), self.peek_next()) {
+            (Some('k'), Some('h')) => Keep::Highest,
+            (Some('k'), Some('l')) => Keep::Lowest,
+            (Some('d'), Some('h')) => Keep::DropHighest,
+            (Some('d'), Some('l')) => Keep::DropLowest,
+            (Some('k'), _) => {
+                self.pos += 1;
+                let n = self.number()?.unwrap_or(1);
+                return self.check_keep(start, n, count).map(|_| Some(Keep::Highest(n)));
+            }
+            _ => return Ok(None),
+        };
+        self.pos += 2;
+        let n = self.number()?.unwrap_or(1);
+        self.check_keep(start, n, count).map(|_| Some(keep(n)))
+    }
+
+    fn check_keep(&self, start: usize, n: u32, count: u32) -> Result<(), ParseError> {
+        if n == 0 || n > count {
+            Err(self.error_at(start, format!("Can only keep or drop 1 to {} dice", count)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Roll, ParseError> {
+    Parser::new(input).roll()
+}
+
+// What a term put in the tray: its dice, already carrying the term's sign and
+// rules, or its signed constant
+pub enum TermResult {
+    Dice { dice: Vec<Die> },
+    Constant(i32),
+}
+
+pub struct RollResult {
+    pub terms: Vec<TermResult>,
+}
+
+impl Expr {
+    // Keep sets are numbered from `first_set` so several groups can share a tray
+    pub fn evaluate(&self, first_set: u32) -> RollResult {
+        let terms = self.terms.iter().enumerate().map(|(i, term)| match term.operand {
+            Operand::Dice { count, kind, explode, reroll, keep } => {
+                let dice: Vec<Die> = (0..count).map(|_| Die::new(kind)).collect();
+                // Each term keeps or drops among its own dice only
+                for die in &dice {
+                    die.keep.set(keep.map(|keep| (first_set + i as u32, keep)));
+                    die.explode.set(explode);
+                    die.set_reroll_rule(reroll);
+                    die.sign.set(term.sign);
+                }
+                TermResult::Dice { dice }
+            }
+            Operand::Constant(n) => TermResult::Constant(term.sign * n as i32),
+        }).collect();
+        RollResult { terms }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terms(input: &str) -> Vec<Term> {
+        let roll = parse(input).ok().expect("should parse");
+        assert_eq!(roll.groups.len(), 1);
+        roll.groups.into_iter().next().unwrap().expr.terms
+    }
+
+    fn dice(count: u32, kind: DieKind) -> Operand {
+        Operand::Dice { count, kind, explode: false, reroll: None, keep: None }
+    }
+
+    fn error(input: &str) -> (usize, String) {
+        match parse(input) {
+            Ok(_) => panic!("{:?} should not parse", input),
+            Err(err) => (err.column, err.message),
+        }
+    }
+
+    #[test]
+    fn plain_dice_and_constants() {
+        let terms = terms("2d6 + d8 - 3");
+        assert!(terms[0] == Term { sign: 1, operand: dice(2, DieKind::Six) });
+        assert!(terms[1] == Term { sign: 1, operand: dice(1, DieKind::Eight) });
+        assert!(terms[2] == Term { sign: -1, operand: Operand::Constant(3) });
+    }
+
+    #[test]
+    fn percentile_and_fudge_dice() {
+        assert!(terms("d%")[0].operand == dice(1, DieKind::Hundred));
+        assert!(terms("4dF")[0].operand == dice(4, DieKind::Fudge));
+        assert!(terms("4df")[0].operand == dice(4, DieKind::Fudge));
+        assert!(terms("3d7")[0].operand == dice(3, DieKind::Custom(7)));
+    }
+
+    #[test]
+    fn explode_reroll_and_keep_suffixes() {
+        let operand = |input| terms(input).remove(0).operand;
+        assert!(operand("3d6!") == Operand::Dice {
+            count: 3, kind: DieKind::Six, explode: true, reroll: None, keep: None,
+        });
+        assert!(operand("2d6r") == Operand::Dice {
+            count: 2, kind: DieKind::Six, explode: false, reroll: Some(Reroll::Until(1)), keep: None,
+        });
+        assert!(operand("2d6ro2") == Operand::Dice {
+            count: 2, kind: DieKind::Six, explode: false, reroll: Some(Reroll::Once(2)), keep: None,
+        });
+        assert!(operand("2d20!r1kh1") == Operand::Dice {
+            count: 2, kind: DieKind::Twenty, explode: true, reroll: Some(Reroll::Until(1)), keep: Some(Keep::Highest(1)),
+        });
+
+        let keep = |input| match terms(input).remove(0).operand {
+            Operand::Dice { keep, .. } => keep,
+            Operand::Constant(_) => panic!("{:?} should be dice", input),
+        };
+        assert!(keep("4d6k3") == Some(Keep::Highest(3)));
+        assert!(keep("4d6k") == Some(Keep::Highest(1)));
+        assert!(keep("4d6kh3") == Some(Keep::Highest(3)));
+        assert!(keep("2d20kl") == Some(Keep::Lowest(1)));
+        assert!(keep("4d6dl") == Some(Keep::DropLowest(1)));
+        assert!(keep("4d6DH2") == Some(Keep::DropHighest(2)));
+        assert!(keep("4dFkh2") == Some(Keep::Highest(2)));
+    }
+
+    #[test]
+    fn leading_and_subtracted_signs() {
+        let terms = terms("-1d4 + 1d20 - 1d4");
+        let signs: Vec<i32> = terms.iter().map(|term| term.sign).collect();
+        assert_eq!(signs, vec![-1, 1, -1]);
+        assert!(terms[2].operand == dice(1, DieKind::Four));
+    }
+
+    #[test]
+    fn named_groups() {
+        let roll = parse("To Hit: 1d20+5; Damage: 2d6+3; 1d4").ok().unwrap();
+        let names: Vec<Option<&str>> = roll.groups.iter().map(|group| group.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("To Hit"), Some("Damage"), None]);
+        assert_eq!(roll.groups[1].expr.terms.len(), 2);
+        assert!(roll.groups[2].expr.terms[0].operand == dice(1, DieKind::Four));
+    }
+
+    #[test]
+    fn dice_count_covers_every_group() {
+        assert_eq!(parse("4d6dl1").ok().unwrap().dice_count(), 4);
+        assert_eq!(parse("To Hit: 1d20+5; Damage: 2d6 - 1d4 + 3").ok().unwrap().dice_count(), 4);
+        assert_eq!(parse("7").ok().unwrap().dice_count(), 0);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for input in ["4d6kh3 + 1d4 - 2", "-1dF + 3d6!ro2dl1", "Attack: 1d20 + 5; Damage: 2d6 + 3", "1d100 - 1d7"] {
+            let roll = parse(input).ok().unwrap();
+            assert_eq!(roll.to_string(), input);
+            assert!(parse(&roll.to_string()).ok().unwrap() == roll);
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(error(""), (0, "Expected a roll".to_string()));
+        assert_eq!(error("1d6;"), (4, "Expected a roll".to_string()));
+        assert_eq!(error("1d"), (2, "Expected number of sides".to_string()));
+        assert_eq!(error("1d6 x"), (4, "Unexpected 'x'".to_string()));
+        assert_eq!(error("1d6 +"), (5, "Expected a number or die".to_string()));
+        assert_eq!(error(": 1d6"), (0, "Expected a group name".to_string()));
+        assert_eq!(error("0d6"), (0, "Dice count must be between 1 and 100".to_string()));
+        assert_eq!(error("101d6"), (0, "Dice count must be between 1 and 100".to_string()));
+        assert_eq!(error("1d1"), (2, "Dice need 2 to 1000 sides".to_string()));
+        assert_eq!(error("1d6r6"), (3, "Can only reroll 1 to 5".to_string()));
+        assert_eq!(error("2d6kh3"), (3, "Can only keep or drop 1 to 2 dice".to_string()));
+        assert_eq!(error("1d6 + 2000000"), (6, "Number is too large".to_string()));
+    }
+}
@@ -0,0 +1,228 @@
+// Interpreters that read a settled tray as a named outcome, like "Weak Hit" on
+// 7–9 in Powered by the Apocalypse games. Besides the presets, user-defined
+// ones live in outcomes.json next to favorites.json, e.g.
+//
+//   [{ "name": "Ironsworn", "reading": "Total", "bands": [
+//       { "name": "Miss", "color": "#E01B24" },
+//       { "name": "Weak Hit", "from": 5, "color": "#F6D32D" }, ... ] }]
+
+use gtk::glib;
+use std::cell::RefCell;
+use std::fs;
+use std::path::PathBuf;
+
+// What the bands are compared against
+#[derive(Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Reading {
+    Total,
+    // The highest die that counted, as in Blades in the Dark
+    Highest,
+}
+
+impl Reading {
+    pub const ALL: [Reading; 2] = [Reading::Total, Reading::Highest];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Reading::Total => "Total",
+            Reading::Highest => "Highest Die",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Band {
+    pub name: String,
+    // Lowest value in the band; None for the band below all the others
+    #[serde(default)]
+    pub from: Option<i32>,
+    pub color: String,
+}
+
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Interpreter {
+    pub name: String,
+    pub reading: Reading,
+    pub bands: Vec<Band>,
+    // Replaces the top band when more than one die shows the highest value
+    #[serde(default)]
+    pub double: Option<Band>,
+}
+
+impl Interpreter {
+    pub fn is_valid(&self) -> bool {
+        !self.name.trim().is_empty() && !self.bands.is_empty()
+    }
+
+    // The band `values` (the kept dice) and their `total` fall in
+    pub fn read(&self, values: &[i32], total: i32) -> Option<&Band> {
+        let value = match self.reading {
+            Reading::Total => total,
+            Reading::Highest => *values.iter().max()?,
+        };
+        let band = self.bands.iter()
+            .filter(|band| band.from.is_none_or(|from| value >= from))
+            .max_by_key(|band| band.from.unwrap_or(i32::MIN))?;
+
+        let is_top = self.bands.iter().all(|b| b.from.unwrap_or(i32::MIN) <= band.from.unwrap_or(i32::MIN));
+        match &self.double {
+            Some(double) if is_top && values.iter().filter(|&&v| v == value).count() > 1 => Some(double),
+            _ => Some(band),
+        }
+    }
+}
+
+fn band(name: &str, from: Option<i32>, color: &str) -> Band {
+    Band { name: name.to_string(), from, color: color.to_string() }
+}
+
+pub fn presets() -> Vec<Interpreter> {
+    vec![
+        Interpreter {
+            name: "Powered by the Apocalypse".to_string(),
+            reading: Reading::Total,
+            bands: vec![
+                band("Miss", None, "#E01B24"),
+                band("Weak Hit", Some(7), "#F6D32D"),
+                band("Strong Hit", Some(10), "#2EC27E"),
+            ],
+            double: None,
+        },
+        Interpreter {
+            name: "Blades in the Dark".to_string(),
+            reading: Reading::Highest,
+            bands: vec![
+                band("Bad Outcome", None, "#E01B24"),
+                band("Partial Success", Some(4), "#F6D32D"),
+                band("Full Success", Some(6), "#2EC27E"),
+            ],
+            double: Some(band("Critical", None, "#3584E4")),
+        },
+        Interpreter {
+            name: "Fate Ladder".to_string(),
+            reading: Reading::Total,
+            bands: vec![
+                band("Terrible", None, "#A51D2D"),
+                band("Poor", Some(-1), "#E01B24"),
+                band("Mediocre", Some(0), "#C64600"),
+                band("Average", Some(1), "#F6D32D"),
+                band("Fair", Some(2), "#C0BF00"),
+                band("Good", Some(3), "#33D17A"),
+                band("Great", Some(4), "#26A269"),
+                band("Superb", Some(5), "#3584E4"),
+                band("Fantastic", Some(6), "#9141AC"),
+                band("Epic", Some(7), "#C061CB"),
+                band("Legendary", Some(8), "#E5A50A"),
+            ],
+            double: None,
+        },
+    ]
+}
+
+thread_local! {
+    static DEFINITIONS: RefCell<Vec<Interpreter>> = RefCell::new(load());
+}
+
+// User-defined interpreters, without the presets
+pub fn definitions() -> Vec<Interpreter> {
+    DEFINITIONS.with(|defs| defs.borrow().clone())
+}
+
+// Presets followed by user-defined interpreters
+pub fn all() -> Vec<Interpreter> {
+    let mut all = presets();
+    all.extend(definitions());
+    all
+}
+
+// Adds an interpreter, replacing any with the same name
+pub fn add(def: Interpreter) {
+    DEFINITIONS.with(|defs| {
+        let mut defs = defs.borrow_mut();
+        match defs.iter_mut().find(|d| d.name == def.name) {
+            Some(existing) => *existing = def,
+            None => defs.push(def),
+        }
+        save(&defs);
+    });
+}
+
+pub fn remove(name: &str) {
+    DEFINITIONS.with(|defs| {
+        let mut defs = defs.borrow_mut();
+        defs.retain(|d| d.name != name);
+        save(&defs);
+    });
+}
+
+// Reads bands written one per line as "Name = from : #color", where the lowest
+// band leaves out its value and the color is optional
+pub fn parse_bands(text: &str) -> Result<Vec<Band>, String> {
+    let bands: Vec<Band> = text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let (band, color) = line.split_once(':').unwrap_or((line, ""));
+            let (name, from) = band.split_once('=').unwrap_or((band, ""));
+            let from = match from.trim() {
+                "" => None,
+                from => Some(from.parse().map_err(|_| format!("Line {}: \"{}\" is not a number", i + 1, from))?),
+            };
+            let color = match color.trim() {
+                "" => "#77767B".to_string(),
+                color if color.starts_with('#') && color.len() == 7 && color[1..].chars().all(|c| c.is_ascii_hexdigit()) => {
+                    color.to_uppercase()
+                }
+                color => return Err(format!("Line {}: \"{}\" is not a color like #2EC27E", i + 1, color)),
+            };
+            Ok(Band { name: name.trim().to_string(), from, color })
+        })
+        .collect::<Result<_, _>>()?;
+
+    if bands.iter().filter(|band| band.from.is_none()).count() > 1 {
+        return Err("Only the lowest band can leave out its value".to_string());
+    }
+    Ok(bands)
+}
+
+// The outcome's name in its color, for labels that use markup
+pub fn markup(band: &Band) -> String {
+    format!(
+        "<span foreground=\"{}\" weight=\"bold\">{}</span>",
+        glib::markup_escape_text(&band.color),
+        glib::markup_escape_text(&band.name),
+    )
+}
+
+fn path() -> PathBuf {
+    let mut path = glib::user_data_dir();
+    path.push("dice");
+    path.push("outcomes.json");
+    path
+}
+
+fn save(defs: &[Interpreter]) {
+    let path = path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    if let Ok(json) = serde_json::to_string_pretty(defs) {
+        fs::write(&path, json).ok();
+    }
+}
+
+fn load() -> Vec<Interpreter> {
+    let defs: Vec<Interpreter> = fs::read_to_string(path())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+    defs.into_iter()
+        .filter(|def| {
+            let valid = def.is_valid();
+            if !valid {
+                println!("Skipping outcome bands \"{}\": they need a name and at least one band", def.name);
+            }
+            valid
+        })
+        .collect()
+}
@@ -1,17 +1,38 @@
-use gtk::{gio, gdk, prelude::*};
+use gtk::{gio, gdk, glib, prelude::*};
 use adw::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
 
-const COLOR_KEYS: [(&str, &str); 6] = [
+use crate::analysis::format_count;
+use crate::custom_dice::{self, CustomDie};
+use crate::dashboard::face_histogram;
+use crate::die::{self, CritRange, DieKind, CRIT_KINDS};
+use crate::fairness::{self, Report};
+use crate::outcomes::{self, Interpreter, Reading};
+
+const COLOR_KEYS: [(&str, &str); 8] = [
     ("color-d4", "D4"),
     ("color-d6", "D6"),
     ("color-d8", "D8"),
     ("color-d10", "D10"),
     ("color-d12", "D12"),
     ("color-d20", "D20"),
+    ("color-d100", "D100"),
+    ("color-custom", "Other Dice"),
 ];
 
 const RNG_VALUES: [&str; 3] = ["chacha", "stdrng", "smallrng"];
 
+const RNG_LABELS: [&str; 3] = ["ChaCha (default)", "StdRng", "SmallRng"];
+
+// Draw indices are u64, but a spin row holds an f64, which only counts every
+// whole number up to 2^53; later draws can't be picked and show as this one
+const MAX_DRAW: u64 = 1 << 53;
+
+const FACES_HINT: &str = "One face per line: label = value : Symbol, Symbol";
+
+const BANDS_HINT: &str = "One band per line from lowest to highest: Name = from : #color. The lowest band leaves out its value.";
+
 pub fn hex_to_rgb(hex: &str) -> [f32; 3] {
     let hex = hex.trim_start_matches('#');
     let r = u8::from_str_radix(&hex[0..2], 16).unwrap_or(0) as f32 / 255.0;
@@ -78,7 +99,7 @@ pub fn build_preferences_dialog() -> adw::PreferencesDialog {
         .title("Random Number Generator")
         .build();
 
-    let model = gtk::StringList::new(&["ChaCha (default)", "StdRng", "SmallRng"]);
+    let model = gtk::StringList::new(&RNG_LABELS);
     let rng_row = adw::ComboRow::builder()
         .title("Algorithm")
         .model(&model)
@@ -97,7 +118,9 @@ pub fn build_preferences_dialog() -> adw::PreferencesDialog {
     });
 
     rng_group.add(&rng_row);
+    rng_group.add(&build_fairness_row(&dialog, &rng_row));
     page.add(&rng_group);
+    page.add(&build_seed_group(&settings));
 
     // History group
     let history_group = adw::PreferencesGroup::builder()
@@ -114,7 +137,599 @@ pub fn build_preferences_dialog() -> adw::PreferencesDialog {
 
     history_group.add(&record_all_row);
     page.add(&history_group);
+    page.add(&build_crit_ranges_group());
+    page.add(&build_custom_dice_group(&dialog));
+    page.add(&build_outcomes_group(&dialog));
     dialog.add(&page);
 
     dialog
 }
+
+// Describes a critical range, e.g. "Crit 19–20 · Fumble 1"
+fn format_crit_range(kind: DieKind, range: CritRange) -> String {
+    let faces = |from: u32, to: u32| if from == to { from.to_string() } else { format!("{}–{}", from, to) };
+    let mut parts = Vec::new();
+    if range.success > 0 {
+        parts.push(format!("Crit {}", faces(range.success, kind.sides())));
+    }
+    if range.failure > 0 {
+        parts.push(format!("Fumble {}", faces(1, range.failure)));
+    }
+    if parts.is_empty() { "Off".to_string() } else { parts.join(" · ") }
+}
+
+fn build_crit_ranges_group() -> adw::PreferencesGroup {
+    let group = adw::PreferencesGroup::builder()
+        .title("Critical Ranges")
+        .description("Faces that count as a critical success or failure. 0 turns either off.")
+        .build();
+
+    for kind in CRIT_KINDS {
+        let range = kind.crit_range();
+        let row = adw::ExpanderRow::builder()
+            .title(format!("D{}", kind.sides()))
+            .subtitle(format_crit_range(kind, range))
+            .build();
+
+        let success_row = adw::SpinRow::with_range(0.0, kind.sides() as f64, 1.0);
+        success_row.set_title("Critical success from");
+        success_row.set_value(range.success as f64);
+        let failure_row = adw::SpinRow::with_range(0.0, kind.sides() as f64, 1.0);
+        failure_row.set_title("Critical failure up to");
+        failure_row.set_value(range.failure as f64);
+
+        for spin_row in [&success_row, &failure_row] {
+            let row = row.clone();
+            let success_row = success_row.clone();
+            let failure_row = failure_row.clone();
+            spin_row.connect_value_notify(move |_| {
+                let range = CritRange {
+                    success: success_row.value() as u32,
+                    failure: failure_row.value() as u32,
+                };
+                die::set_crit_range(kind, range);
+                row.set_subtitle(&format_crit_range(kind, range));
+            });
+        }
+
+        row.add_row(&success_row);
+        row.add_row(&failure_row);
+        group.add(&row);
+    }
+
+    group
+}
+
+fn build_custom_dice_group(dialog: &adw::PreferencesDialog) -> adw::PreferencesGroup {
+    let group = adw::PreferencesGroup::builder()
+        .title("Custom Dice")
+        .description("Dice with your own face labels, values and symbols")
+        .build();
+
+    let add_button = gtk::Button::builder()
+        .icon_name("list-add-symbolic")
+        .valign(gtk::Align::Center)
+        .css_classes(vec!["flat"])
+        .tooltip_text("Add Custom Die")
+        .build();
+    group.set_header_suffix(Some(&add_button));
+
+    let rows: Rc<RefCell<Vec<adw::ActionRow>>> = Rc::new(RefCell::new(Vec::new()));
+    refresh_custom_dice_rows(&group, &rows);
+
+    let dialog = dialog.downgrade();
+    let group_clone = group.clone();
+    add_button.connect_clicked(move |_| {
+        if let Some(dialog) = dialog.upgrade() {
+            let group = group_clone.clone();
+            let rows = rows.clone();
+            show_custom_die_editor(&dialog, move || refresh_custom_dice_rows(&group, &rows));
+        }
+    });
+
+    group
+}
+
+fn refresh_custom_dice_rows(group: &adw::PreferencesGroup, rows: &Rc<RefCell<Vec<adw::ActionRow>>>) {
+    for row in rows.borrow_mut().drain(..) {
+        group.remove(&row);
+    }
+
+    for def in custom_dice::definitions() {
+        let row = adw::ActionRow::builder()
+            .title(glib::markup_escape_text(&def.name).as_str())
+            .subtitle(format!("{} faces on a D{}", def.faces.len(), def.mesh.sides()))
+            .build();
+
+        let delete_button = gtk::Button::builder()
+            .icon_name("user-trash-symbolic")
+            .valign(gtk::Align::Center)
+            .css_classes(vec!["flat"])
+            .tooltip_text("Delete")
+            .build();
+
+        let id = def.id();
+        let group_clone = group.clone();
+        let rows_clone = rows.clone();
+        delete_button.connect_clicked(move |_| {
+            custom_dice::remove(id);
+            refresh_custom_dice_rows(&group_clone, &rows_clone);
+        });
+        row.add_suffix(&delete_button);
+
+        group.add(&row);
+        rows.borrow_mut().push(row);
+    }
+}
+
+fn show_custom_die_editor(parent: &adw::PreferencesDialog, on_saved: impl Fn() + 'static) {
+    let name_row = adw::EntryRow::builder()
+        .title("Name")
+        .build();
+
+    let shapes: Vec<String> = custom_dice::MESHES.iter().map(|mesh| format!("D{}", mesh.sides())).collect();
+    let shapes: Vec<&str> = shapes.iter().map(String::as_str).collect();
+    let mesh_row = adw::ComboRow::builder()
+        .title("Shape")
+        .model(&gtk::StringList::new(&shapes))
+        .selected(1)
+        .build();
+
+    let list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(vec!["boxed-list"])
+        .build();
+    list.append(&name_row);
+    list.append(&mesh_row);
+
+    let faces_view = gtk::TextView::builder()
+        .monospace(true)
+        .top_margin(8)
+        .bottom_margin(8)
+        .left_margin(8)
+        .right_margin(8)
+        .build();
+    let faces_scroll = gtk::ScrolledWindow::builder()
+        .min_content_height(160)
+        .child(&faces_view)
+        .css_classes(vec!["card"])
+        .build();
+
+    let hint = gtk::Label::builder()
+        .label(FACES_HINT)
+        .wrap(true)
+        .xalign(0.0)
+        .css_classes(vec!["caption", "dim-label"])
+        .build();
+
+    let content = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(12)
+        .build();
+    content.append(&list);
+    content.append(&faces_scroll);
+    content.append(&hint);
+
+    let dialog = adw::AlertDialog::builder()
+        .heading("New Custom Die")
+        .extra_child(&content)
+        .build();
+    dialog.add_responses(&[("cancel", "Cancel"), ("save", "Save")]);
+    dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("save"));
+    dialog.set_close_response("cancel");
+    dialog.set_response_enabled("save", false);
+
+    let build = Rc::new(glib::clone!(#[weak] name_row, #[weak] mesh_row, #[weak] faces_view, #[upgrade_or] Err(String::new()), move || {
+        let mesh = custom_dice::MESHES[(mesh_row.selected() as usize).min(custom_dice::MESHES.len() - 1)];
+        let buffer = faces_view.buffer();
+        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+        let def = CustomDie {
+            name: name_row.text().trim().to_string(),
+            mesh,
+            faces: custom_dice::parse_faces(&text)?,
+        };
+        if def.name.is_empty() {
+            Err("Give the die a name".to_string())
+        } else if def.faces.len() != mesh.sides() as usize {
+            Err(format!("A D{} needs {} faces, not {}", mesh.sides(), mesh.sides(), def.faces.len()))
+        } else {
+            Ok(def)
+        }
+    }));
+
+    let validate = {
+        let build = build.clone();
+        let dialog = dialog.downgrade();
+        let hint = hint.downgrade();
+        Rc::new(move || {
+            let (Some(dialog), Some(hint)) = (dialog.upgrade(), hint.upgrade()) else { return };
+            match build() {
+                Ok(_) => {
+                    dialog.set_response_enabled("save", true);
+                    hint.set_label(FACES_HINT);
+                }
+                Err(message) => {
+                    dialog.set_response_enabled("save", false);
+                    if !message.is_empty() {
+                        hint.set_label(&message);
+                    }
+                }
+            }
+        })
+    };
+
+    let v = validate.clone();
+    name_row.connect_changed(move |_| v());
+    let v = validate.clone();
+    mesh_row.connect_selected_notify(move |_| v());
+    let v = validate.clone();
+    faces_view.buffer().connect_changed(move |_| v());
+
+    dialog.connect_response(None, move |_, response| {
+        if response == "save" {
+            if let Ok(def) = build() {
+                custom_dice::add(def);
+                on_saved();
+            }
+        }
+    });
+
+    dialog.present(Some(parent));
+}
+
+fn build_outcomes_group(dialog: &adw::PreferencesDialog) -> adw::PreferencesGroup {
+    let presets: Vec<String> = outcomes::presets().into_iter().map(|preset| preset.name).collect();
+    let group = adw::PreferencesGroup::builder()
+        .title("Outcome Bands")
+        .description(format!("Your own ways of reading a roll as an outcome, alongside {}", presets.join(", ")))
+        .build();
+
+    let add_button = gtk::Button::builder()
+        .icon_name("list-add-symbolic")
+        .valign(gtk::Align::Center)
+        .css_classes(vec!["flat"])
+        .tooltip_text("Add Outcome Bands")
+        .build();
+    group.set_header_suffix(Some(&add_button));
+
+    let rows: Rc<RefCell<Vec<adw::ActionRow>>> = Rc::new(RefCell::new(Vec::new()));
+    refresh_outcome_rows(&group, &rows);
+
+    let dialog = dialog.downgrade();
+    let group_clone = group.clone();
+    add_button.connect_clicked(move |_| {
+        if let Some(dialog) = dialog.upgrade() {
+            let group = group_clone.clone();
+            let rows = rows.clone();
+            show_outcome_editor(&dialog, move || refresh_outcome_rows(&group, &rows));
+        }
+    });
+
+    group
+}
+
+fn refresh_outcome_rows(group: &adw::PreferencesGroup, rows: &Rc<RefCell<Vec<adw::ActionRow>>>) {
+    for row in rows.borrow_mut().drain(..) {
+        group.remove(&row);
+    }
+
+    for def in outcomes::definitions() {
+        let bands: Vec<String> = def.bands.iter().map(outcomes::markup).collect();
+        let row = adw::ActionRow::builder()
+            .title(glib::markup_escape_text(&def.name).as_str())
+            .subtitle(format!("{} · {}", def.reading.label(), bands.join(" · ")))
+            .build();
+
+        let delete_button = gtk::Button::builder()
+            .icon_name("user-trash-symbolic")
+            .valign(gtk::Align::Center)
+            .css_classes(vec!["flat"])
+            .tooltip_text("Delete")
+            .build();
+
+        let name = def.name.clone();
+        let group_clone = group.clone();
+        let rows_clone = rows.clone();
+        delete_button.connect_clicked(move |_| {
+            outcomes::remove(&name);
+            refresh_outcome_rows(&group_clone, &rows_clone);
+        });
+        row.add_suffix(&delete_button);
+
+        group.add(&row);
+        rows.borrow_mut().push(row);
+    }
+}
+
+fn show_outcome_editor(parent: &adw::PreferencesDialog, on_saved: impl Fn() + 'static) {
+    let name_row = adw::EntryRow::builder()
+        .title("Name")
+        .build();
+
+    let readings: Vec<&str> = Reading::ALL.iter().map(|reading| reading.label()).collect();
+    let reading_row = adw::ComboRow::builder()
+        .title("Read")
+        .model(&gtk::StringList::new(&readings))
+        .build();
+
+    let list = gtk::ListBox::builder()
+        .selection_mode(gtk::SelectionMode::None)
+        .css_classes(vec!["boxed-list"])
+        .build();
+    list.append(&name_row);
+    list.append(&reading_row);
+
+    let bands_view = gtk::TextView::builder()
+        .monospace(true)
+        .top_margin(8)
+        .bottom_margin(8)
+        .left_margin(8)
+        .right_margin(8)
+        .build();
+    let bands_scroll = gtk::ScrolledWindow::builder()
+        .min_content_height(160)
+        .child(&bands_view)
+        .css_classes(vec!["card"])
+        .build();
+
+    let hint = gtk::Label::builder()
+        .label(BANDS_HINT)
+        .wrap(true)
+        .xalign(0.0)
+        .css_classes(vec!["caption", "dim-label"])
+        .build();
+
+    let content = gtk::Box::builder()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(12)
+        .build();
+    content.append(&list);
+    content.append(&bands_scroll);
+    content.append(&hint);
+
+    let dialog = adw::AlertDialog::builder()
+        .heading("New Outcome Bands")
+        .extra_child(&content)
+        .build();
+    dialog.add_responses(&[("cancel", "Cancel"), ("save", "Save")]);
+    dialog.set_response_appearance("save", adw::ResponseAppearance::Suggested);
+    dialog.set_default_response(Some("save"));
+    dialog.set_close_response("cancel");
+    dialog.set_response_enabled("save", false);
+
+    let build = Rc::new(glib::clone!(#[weak] name_row, #[weak] reading_row, #[weak] bands_view, #[upgrade_or] Err(String::new()), move || {
+        let buffer = bands_view.buffer();
+        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+        let def = Interpreter {
+            name: name_row.text().trim().to_string(),
+            reading: Reading::ALL[(reading_row.selected() as usize).min(Reading::ALL.len() - 1)],
+            bands: outcomes::parse_bands(&text)?,
+            double: None,
+        };
+        if def.name.is_empty() {
+            Err("Give the bands a name".to_string())
+        } else if outcomes::presets().iter().any(|preset| preset.name == def.name) {
+            Err(format!("\"{}\" is already a preset", def.name))
+        } else if def.bands.is_empty() {
+            Err("Add at least one band".to_string())
+        } else {
+            Ok(def)
+        }
+    }));
+
+    let validate = {
+        let build = build.clone();
+        let dialog = dialog.downgrade();
+        let hint = hint.downgrade();
+        Rc::new(move || {
+            let (Some(dialog), Some(hint)) = (dialog.upgrade(), hint.upgrade()) else { return };
+            match build() {
+                Ok(_) => {
+                    dialog.set_response_enabled("save", true);
+                    hint.set_label(BANDS_HINT);
+                }
+                Err(message) => {
+                    dialog.set_response_enabled("save", false);
+                    if !message.is_empty() {
+                        hint.set_label(&message);
+                    }
+                }
+            }
+        })
+    };
+
+    let v = validate.clone();
+    name_row.connect_changed(move |_| v());
+    let v = validate.clone();
+    reading_row.connect_selected_notify(move |_| v());
+    let v = validate.clone();
+    bands_view.buffer().connect_changed(move |_| v());
+
+    dialog.connect_response(None, move |_, response| {
+        if response == "save" {
+            if let Ok(def) = build() {
+                outcomes::add(def);
+                on_saved();
+            }
+        }
+    });
+
+    dialog.present(Some(parent));
+}
+
+fn build_seed_group(settings: &gio::Settings) -> adw::PreferencesGroup {
+    let group = adw::PreferencesGroup::builder()
+        .title("Seeded Rolls")
+        .description("The same seed gives the same rolls, starting over whenever the app starts or the seed changes. Each roll in Recents shows its seed and draws when hovered.")
+        .build();
+
+    let seeded_row = adw::SwitchRow::builder()
+        .title("Roll from a Seed")
+        .build();
+    settings.bind("seeded-rolls", &seeded_row, "active")
+        .build();
+
+    let seed_row = adw::EntryRow::builder()
+        .title("Seed")
+        .text(settings.uint64("roll-seed").to_string())
+        .show_apply_button(true)
+        .input_purpose(gtk::InputPurpose::Digits)
+        .build();
+    let new_seed_button = gtk::Button::builder()
+        .icon_name("view-refresh-symbolic")
+        .tooltip_text("New Seed")
+        .valign(gtk::Align::Center)
+        .css_classes(vec!["flat"])
+        .build();
+    seed_row.add_suffix(&new_seed_button);
+
+    let draw_row = adw::SpinRow::with_range(0.0, MAX_DRAW as f64, 1.0);
+    draw_row.set_title("Next Draw");
+    draw_row.set_subtitle("Set to a roll's first draw and roll the same dice to get it again");
+    draw_row.set_value(next_draw());
+
+    for row in [seed_row.upcast_ref::<gtk::Widget>(), draw_row.upcast_ref()] {
+        seeded_row.bind_property("active", row, "sensitive")
+            .sync_create()
+            .build();
+    }
+
+    let settings_clone = settings.clone();
+    let draw_row_weak = draw_row.downgrade();
+    seed_row.connect_apply(move |row| {
+        match row.text().trim().parse::<u64>() {
+            Ok(seed) => {
+                row.remove_css_class("error");
+                settings_clone.set_uint64("roll-seed", seed).ok();
+                if let Some(draw_row) = draw_row_weak.upgrade() {
+                    draw_row.set_value(next_draw());
+                }
+            }
+            Err(_) => row.add_css_class("error"),
+        }
+    });
+
+    let seed_row_weak = seed_row.downgrade();
+    new_seed_button.connect_clicked(move |_| {
+        if let Some(seed_row) = seed_row_weak.upgrade() {
+            seed_row.set_text(&rand::random::<u64>().to_string());
+            seed_row.emit_by_name::<()>("apply", &[]);
+        }
+    });
+
+    draw_row.connect_value_notify(|row| {
+        die::seek(row.value() as u64);
+    });
+
+    group.add(&seeded_row);
+    group.add(&seed_row);
+    group.add(&draw_row);
+    group
+}
+
+// The draw seeded rolls take next, as the draw row shows it
+fn next_draw() -> f64 {
+    die::sequence().map_or(0, |(_, index)| index).min(MAX_DRAW) as f64
+}
+
+fn build_fairness_row(dialog: &adw::PreferencesDialog, rng_row: &adw::ComboRow) -> adw::ActionRow {
+    let row = adw::ActionRow::builder()
+        .title("Fairness")
+        .subtitle(format!("Roll every die {} times and check the results look random", format_count(fairness::SAMPLES)))
+        .build();
+    let spinner = gtk::Spinner::new();
+    let test_button = gtk::Button::builder()
+        .label("Test")
+        .valign(gtk::Align::Center)
+        .build();
+    row.add_suffix(&spinner);
+    row.add_suffix(&test_button);
+
+    let dialog = dialog.downgrade();
+    let rng_row = rng_row.downgrade();
+    test_button.connect_clicked(move |button| {
+        let Some(rng_row) = rng_row.upgrade() else { return };
+        let idx = (rng_row.selected() as usize).min(RNG_VALUES.len() - 1);
+        let (algorithm, label) = (RNG_VALUES[idx], RNG_LABELS[idx]);
+
+        button.set_sensitive(false);
+        spinner.start();
+        let (dialog, button, spinner) = (dialog.clone(), button.downgrade(), spinner.downgrade());
+        glib::spawn_future_local(async move {
+            let reports = gio::spawn_blocking(move || fairness::run(algorithm)).await;
+            if let (Some(button), Some(spinner)) = (button.upgrade(), spinner.upgrade()) {
+                button.set_sensitive(true);
+                spinner.stop();
+            }
+            if let (Ok(reports), Some(dialog)) = (reports, dialog.upgrade()) {
+                dialog.push_subpage(&build_fairness_page(label, &reports));
+            }
+        });
+    });
+
+    row
+}
+
+fn build_fairness_page(algorithm: &str, reports: &[Report]) -> adw::NavigationPage {
+    let page = adw::PreferencesPage::new();
+
+    let failed = reports.iter().filter(|report| !report.passed()).count();
+    let summary = adw::PreferencesGroup::builder()
+        .title(match failed {
+            0 => "Every Die Passed".to_string(),
+            n => format!("{} of {} Dice Failed", n, reports.len()),
+        })
+        .description(format!(
+            "{} rolls of each die with {}. A test fails when a fair die would do as badly less than once in {} tries.",
+            format_count(fairness::SAMPLES),
+            algorithm,
+            format_count((1.0 / fairness::ALPHA).round() as u64),
+        ))
+        .build();
+    page.add(&summary);
+
+    let verdict = |passed: bool, text: String| {
+        let label = gtk::Label::new(Some(&text));
+        label.add_css_class(if passed { "success" } else { "error" });
+        label
+    };
+
+    for report in reports {
+        let group = adw::PreferencesGroup::builder()
+            .title(glib::markup_escape_text(&report.kind.name()).as_str())
+            .header_suffix(&verdict(report.passed(), if report.passed() { "Passed" } else { "Failed" }.to_string()))
+            .build();
+        group.add(&face_histogram(report.counts.clone()));
+
+        let list = gtk::ListBox::builder()
+            .selection_mode(gtk::SelectionMode::None)
+            .css_classes(vec!["boxed-list"])
+            .margin_top(12)
+            .build();
+        let chi_square_row = adw::ActionRow::builder()
+            .title("Chi-Square")
+            .subtitle(format!("χ² = {:.2} with {} degrees of freedom", report.chi_square, report.degrees_of_freedom()))
+            .build();
+        chi_square_row.add_suffix(&verdict(report.chi_square_passed(), format!("p = {:.3}", report.chi_square_p)));
+        list.append(&chi_square_row);
+
+        let runs_row = adw::ActionRow::builder()
+            .title("Runs")
+            .subtitle(format!("{} runs above or below the middle, {:.0} expected", format_count(report.runs), report.expected_runs))
+            .build();
+        runs_row.add_suffix(&verdict(report.runs_passed(), format!("p = {:.3}", report.runs_p)));
+        list.append(&runs_row);
+        group.add(&list);
+
+        page.add(&group);
+    }
+
+    let toolbar = adw::ToolbarView::new();
+    toolbar.add_top_bar(&adw::HeaderBar::new());
+    toolbar.set_content(Some(&page));
+    adw::NavigationPage::builder()
+        .title("Fairness")
+        .child(&toolbar)
+        .build()
+}
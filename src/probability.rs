@@ -0,0 +1,380 @@
+// Exact odds of a roll's total, worked out without rolling anything. Dice that
+// are simply added up are convolved one at a time; sets that keep or drop dice
+// are enumerated instead, up to a limit so a huge pool can't hang the window.
+
+use std::collections::BTreeMap;
+
+use crate::dice_area::MAX_EXPLODE_DEPTH;
+use crate::die::{DieKind, Keep, Pool, Reroll};
+use crate::notation::{Operand, Roll};
+
+// Ways of rolling a keep set that may be enumerated before giving up
+const MAX_COMBINATIONS: f64 = 250_000.0;
+
+// How a single die is rolled
+#[derive(Clone, Copy, PartialEq)]
+pub struct DieSpec {
+    pub kind: DieKind,
+    pub explode: bool,
+    pub reroll: Option<Reroll>,
+    // Always shows its highest face, like the extra dice of a Max + Roll crit
+    pub fixed: bool,
+}
+
+// Dice kept or dropped together, added to or taken from the total
+#[derive(Clone, PartialEq)]
+pub struct DiceSet {
+    pub sign: i32,
+    pub dice: Vec<DieSpec>,
+    pub keep: Option<Keep>,
+}
+
+// A die as the tray holds it: its keep set and rule, whether it's taken away
+// from the total, and how it rolls
+pub struct TrayDie {
+    pub keep: Option<(u32, Keep)>,
+    pub sign: i32,
+    pub spec: DieSpec,
+}
+
+// Everything that decides a roll's total
+#[derive(Clone, PartialEq, Default)]
+pub struct RollDefinition {
+    pub sets: Vec<DiceSet>,
+    pub modifier: i32,
+    // The total, modifier included, counts twice
+    pub double: bool,
+    // Counts successes instead of adding up faces
+    pub pool: Option<Pool>,
+}
+
+impl RollDefinition {
+    // Every group of the roll added into one total, its dice in the keep sets
+    // rolling the notation into the tray gives them
+    pub fn from_roll(roll: &Roll) -> Self {
+        let mut dice = Vec::new();
+        let mut modifier = 0;
+        let mut first_set = 1;
+        for group in &roll.groups {
+            for (i, term) in group.expr.terms.iter().enumerate() {
+                match term.operand {
+                    Operand::Dice { count, kind, explode, reroll, keep } => dice.extend((0..count).map(|_| TrayDie {
+                        keep: keep.map(|keep| (first_set + i as u32, keep)),
+                        sign: term.sign,
+                        spec: DieSpec { kind, explode, reroll, fixed: false },
+                    })),
+                    Operand::Constant(n) => modifier += term.sign * n as i32,
+                }
+            }
+            first_set += group.expr.terms.len() as u32;
+        }
+        Self::from_tray(dice, modifier, false, None)
+    }
+
+    // Dice with no keep rule are added up as one set per sign, the rest by their keep set
+    pub fn from_tray(dice: impl IntoIterator<Item = TrayDie>, modifier: i32, double: bool, pool: Option<Pool>) -> Self {
+        let mut sets: BTreeMap<(Option<u32>, i32), DiceSet> = BTreeMap::new();
+        for die in dice {
+            sets.entry((die.keep.map(|(set, _)| set), die.sign))
+                .or_insert_with(|| DiceSet { sign: die.sign, dice: Vec::new(), keep: die.keep.map(|(_, keep)| keep) })
+                .dice
+                .push(die.spec);
+        }
+        Self { sets: sets.into_values().collect(), modifier, double, pool }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.iter().all(|set| set.dice.is_empty())
+    }
+
+    pub fn distribution(&self) -> Result<Distribution, String> {
+        let mut dist = Distribution::constant(self.modifier);
+        for set in &self.sets {
+            dist = dist.convolve(&set_distribution(set, self.pool)?);
+        }
+        // Pool mode adds the modifier to the successes, and crit damage doesn't apply
+        if self.double && self.pool.is_none() {
+            dist = dist.map(|total| total * 2);
+        }
+        Ok(dist)
+    }
+}
+
+// The chance of each total a roll can come to
+#[derive(Clone)]
+pub struct Distribution {
+    odds: BTreeMap<i32, f64>,
+}
+
+impl Distribution {
+    fn constant(total: i32) -> Self {
+        Self { odds: BTreeMap::from([(total, 1.0)]) }
+    }
+
+    // How often each total came up, as a share of every roll counted
+    pub fn from_counts(counts: &BTreeMap<i32, u64>) -> Self {
+        let rolls = counts.values().sum::<u64>().max(1) as f64;
+        Self { odds: counts.iter().map(|(&total, &count)| (total, count as f64 / rolls)).collect() }
+    }
+
+    fn convolve(&self, other: &Distribution) -> Self {
+        let mut odds = BTreeMap::new();
+        for (&a, &p) in &self.odds {
+            for (&b, &q) in &other.odds {
+                *odds.entry(a + b).or_insert(0.0) += p * q;
+            }
+        }
+        Self { odds }
+    }
+
+    fn map(&self, f: impl Fn(i32) -> i32) -> Self {
+        let mut odds = BTreeMap::new();
+        for (&total, &p) in &self.odds {
+            *odds.entry(f(total)).or_insert(0.0) += p;
+        }
+        Self { odds }
+    }
+
+    pub fn odds(&self) -> &BTreeMap<i32, f64> {
+        &self.odds
+    }
+
+    pub fn min(&self) -> i32 {
+        self.odds.keys().next().copied().unwrap_or(0)
+    }
+
+    pub fn max(&self) -> i32 {
+        self.odds.keys().next_back().copied().unwrap_or(0)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.odds.iter().map(|(&total, &p)| total as f64 * p).sum()
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        self.odds.iter()
+            .map(|(&total, &p)| (total as f64 - mean).powi(2) * p)
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    pub fn at_least(&self, target: i32) -> f64 {
+        self.odds.range(target..).map(|(_, &p)| p).sum()
+    }
+}
+
+// One way a die can finish: its faces added up, which keep rules compare, what
+// it adds to the total, and the chance of it. An exploding die's chain counts as one
+type Outcome = (u32, i32, f64);
+
+// The faces added up and value of each die drawn so far
+type Chosen = Vec<(u32, i32)>;
+
+fn outcomes(spec: DieSpec, pool: Option<Pool>) -> Vec<Outcome> {
+    let value = |face: u32| pool.map_or_else(|| spec.kind.value(face), |pool| pool.successes(face));
+    if spec.fixed {
+        let face = spec.kind.max_face();
+        return vec![(face, value(face), 1.0)];
+    }
+
+    let sides = spec.kind.faces().count();
+    let odds = match spec.reroll {
+        Some(rule) => rule.face_odds(spec.kind),
+        None => vec![1.0 / sides as f64; sides],
+    };
+
+    let mut merged: BTreeMap<(u32, i32), f64> = BTreeMap::new();
+    // Only a chain of highest faces carries on exploding
+    let explode_face = spec.kind.max_face();
+    let mut chain = Some((0, 0, 1.0));
+    for depth in 0..=MAX_EXPLODE_DEPTH {
+        let Some((key, val, p)) = chain.take() else { break };
+        for (face, &q) in spec.kind.faces().zip(&odds) {
+            let outcome = (key + face, val + value(face), p * q);
+            if spec.explode && face == explode_face && depth < MAX_EXPLODE_DEPTH {
+                chain = Some(outcome);
+            } else {
+                *merged.entry((outcome.0, outcome.1)).or_insert(0.0) += outcome.2;
+            }
+        }
+    }
+    merged.into_iter().map(|((key, val), p)| (key, val, p)).collect()
+}
+
+fn set_distribution(set: &DiceSet, pool: Option<Pool>) -> Result<Distribution, String> {
+    let Some(keep) = set.keep else {
+        let dist = set.dice.iter().fold(Distribution::constant(0), |dist, &spec| {
+            let mut odds = BTreeMap::new();
+            for (_, val, p) in outcomes(spec, pool) {
+                *odds.entry(val).or_insert(0.0) += p;
+            }
+            dist.convolve(&Distribution { odds })
+        });
+        return Ok(dist.map(|total| total * set.sign));
+    };
+
+    // Identical dice are drawn as a multiset, so their order isn't enumerated
+    let mut kinds: Vec<(DieSpec, u32)> = Vec::new();
+    for &spec in &set.dice {
+        match kinds.iter_mut().find(|(s, _)| *s == spec) {
+            Some((_, count)) => *count += 1,
+            None => kinds.push((spec, 1)),
+        }
+    }
+    let draws: Vec<(Vec<Outcome>, u32)> = kinds.into_iter()
+        .map(|(spec, count)| (outcomes(spec, pool), count))
+        .collect();
+
+    let combinations: f64 = draws.iter()
+        .map(|(outcomes, count)| binomial(count + outcomes.len() as u32 - 1, *count))
+        .product();
+    if combinations > MAX_COMBINATIONS {
+        return Err("Too many ways to keep these dice to work out exactly".to_string());
+    }
+
+    let mut odds = BTreeMap::new();
+    enumerate(&draws, &mut Vec::new(), 1.0, keep, &mut odds);
+    Ok(Distribution { odds }.map(|total| total * set.sign))
+}
+
+// Adds the kept total of every combination of `draws` to `odds`
+fn enumerate(draws: &[(Vec<Outcome>, u32)], chosen: &mut Chosen, p: f64, keep: Keep, odds: &mut BTreeMap<i32, f64>) {
+    let Some(((outcomes, count), rest)) = draws.split_first() else {
+        let keys: Vec<u32> = chosen.iter().map(|&(key, _)| key).collect();
+        let total: i32 = chosen.iter()
+            .zip(keep.apply(&keys))
+            .filter(|&(_, kept)| kept)
+            .map(|(&(_, val), _)| val)
+            .sum();
+        *odds.entry(total).or_insert(0.0) += p;
+        return;
+    };
+    choose(outcomes, *count, chosen, p, &mut |chosen, p| enumerate(rest, chosen, p, keep, odds));
+}
+
+// Calls `then` with every multiset of `n` outcomes appended to `chosen`, and its chance
+fn choose(outcomes: &[Outcome], n: u32, chosen: &mut Chosen, p: f64, then: &mut dyn FnMut(&mut Chosen, f64)) {
+    let Some((&(key, val, q), rest)) = outcomes.split_first() else {
+        if n == 0 {
+            then(chosen, p);
+        }
+        return;
+    };
+
+    let len = chosen.len();
+    // The last outcome takes whatever draws are left
+    let counts = if rest.is_empty() { n..=n } else { 0..=n };
+    for c in counts {
+        chosen.truncate(len);
+        chosen.extend(std::iter::repeat_n((key, val), c as usize));
+        choose(rest, n - c, chosen, p * binomial(n, c) * q.powi(c as i32), then);
+    }
+    chosen.truncate(len);
+}
+
+fn binomial(n: u32, k: u32) -> f64 {
+    (0..k.min(n - k)).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+
+    fn die(kind: DieKind, sign: i32, keep: Option<(u32, Keep)>, explode: bool) -> TrayDie {
+        TrayDie { keep, sign, spec: DieSpec { kind, explode, reroll: None, fixed: false } }
+    }
+
+    // The dice rolling the notation leaves in the tray, as `DiceArea::roll_definition` sees them
+    fn tray(dice: Vec<TrayDie>, modifier: i32) -> RollDefinition {
+        RollDefinition::from_tray(dice, modifier, false, None)
+    }
+
+    #[test]
+    fn subtracted_dice_match_the_tray() {
+        let typed = RollDefinition::from_roll(&notation::parse("1d20 - 1d4 + 2").ok().unwrap());
+        let rolled = tray(vec![die(DieKind::Twenty, 1, None, false), die(DieKind::Four, -1, None, false)], 2);
+        assert!(typed == rolled);
+
+        let dist = typed.distribution().unwrap();
+        assert_eq!(dist.min(), -1);
+        assert_eq!(dist.max(), 21);
+        assert!((dist.mean() - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn grouped_rolls_match_the_tray() {
+        let typed = RollDefinition::from_roll(&notation::parse("4d6dl1 - 1d6 + 3; Damage: 2d6! - 1d4kh1").ok().unwrap());
+        // Keep sets count up from 1 through every term of every group
+        let mut dice: Vec<TrayDie> = (0..4).map(|_| die(DieKind::Six, 1, Some((1, Keep::DropLowest(1))), false)).collect();
+        dice.push(die(DieKind::Six, -1, None, false));
+        dice.extend((0..2).map(|_| die(DieKind::Six, 1, None, true)));
+        dice.push(die(DieKind::Four, -1, Some((5, Keep::Highest(1))), false));
+        let rolled = tray(dice, 3);
+        assert!(typed == rolled);
+
+        let typed = typed.distribution().unwrap();
+        let rolled = rolled.distribution().unwrap();
+        assert_eq!(typed.odds().len(), rolled.odds().len());
+        assert!(typed.odds().iter().zip(rolled.odds()).all(|((a, p), (b, q))| a == b && (p - q).abs() < 1e-12));
+    }
+
+    fn typed(input: &str) -> Distribution {
+        RollDefinition::from_roll(&notation::parse(input).ok().unwrap()).distribution().unwrap()
+    }
+
+    fn assert_odds(dist: &Distribution, total: i32, expected: f64) {
+        let p = dist.odds().get(&total).copied().unwrap_or(0.0);
+        assert!((p - expected).abs() < 1e-12, "P({}) = {}, expected {}", total, p, expected);
+    }
+
+    #[test]
+    fn added_dice_are_convolved() {
+        let dist = typed("2d6");
+        assert_eq!(dist.odds().len(), 11);
+        assert_odds(&dist, 2, 1.0 / 36.0);
+        assert_odds(&dist, 7, 6.0 / 36.0);
+        assert!((dist.odds().values().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn kept_dice_are_enumerated() {
+        let dist = typed("2d20kh1");
+        assert_odds(&dist, 20, 39.0 / 400.0);
+        assert_odds(&dist, 1, 1.0 / 400.0);
+        assert!((dist.mean() - 13.825).abs() < 1e-9);
+
+        let dist = typed("4d6kh3");
+        assert_odds(&dist, 18, 21.0 / 1296.0);
+        assert_odds(&dist, 3, 1.0 / 1296.0);
+        assert!((dist.mean() - 15869.0 / 1296.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn exploding_and_rerolled_dice() {
+        // A six always rolls again, so 6 itself can't come up
+        let dist = typed("1d6!");
+        assert_odds(&dist, 6, 0.0);
+        assert_odds(&dist, 7, 1.0 / 36.0);
+        assert!((dist.mean() - 4.2).abs() < 1e-6);
+
+        let dist = typed("1d6ro1");
+        assert_odds(&dist, 1, 1.0 / 36.0);
+        assert!((dist.mean() - 141.0 / 36.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn huge_keep_sets_are_refused() {
+        let roll = notation::parse("100d100kh1").ok().unwrap();
+        assert!(RollDefinition::from_roll(&roll).distribution().is_err());
+    }
+
+    #[test]
+    fn binomial_coefficients() {
+        assert_eq!(binomial(5, 0), 1.0);
+        assert_eq!(binomial(5, 2), 10.0);
+        assert_eq!(binomial(5, 5), 1.0);
+        assert_eq!(binomial(52, 5), 2_598_960.0);
+    }
+}
@@ -1,4 +1,6 @@
-use crate::die::DieKind;
+use crate::custom_dice;
+use crate::die::{Advantage, Crit, CritRule, DieKind, Keep, Pool, Reroll, Target};
+use crate::outcomes::{Band, Interpreter};
 use gtk::glib;
 use std::collections::BTreeMap;
 use std::fs;
@@ -8,7 +10,126 @@ use std::path::PathBuf;
 pub struct RollEntry {
     pub id: u64,
     pub dice: Vec<(DieKind, u32)>,
-    pub total: u32,
+    pub total: i32,
+    // Whether each die in `dice` counted; empty when all of them did
+    #[serde(default)]
+    pub kept: Vec<bool>,
+    #[serde(default)]
+    pub keep: Option<Keep>,
+    // Advantage mode of each die in `dice`; empty when no pairs were rolled
+    #[serde(default)]
+    pub advantage: Vec<Option<Advantage>>,
+    // Whether each die in `dice` was taken away from the total; empty when none was
+    #[serde(default)]
+    pub negative: Vec<bool>,
+    // Extra rolls of each exploding die in `dice`, None for dice that don't explode;
+    // empty when none of them do
+    #[serde(default)]
+    pub chains: Vec<Option<Vec<u32>>>,
+    // Values each die in `dice` replaced by being rerolled on its own, oldest first;
+    // empty when no die was
+    #[serde(default)]
+    pub rerolls: Vec<Vec<u32>>,
+    #[serde(default)]
+    pub reroll_rule: Option<Reroll>,
+    #[serde(default)]
+    pub modifier: i32,
+    #[serde(default)]
+    pub expression: Option<String>,
+    #[serde(default)]
+    pub pool: Option<Pool>,
+    // Success count of a pool roll, which `total` also holds
+    #[serde(default)]
+    pub successes: Option<i32>,
+    #[serde(default)]
+    pub target: Option<Target>,
+    // Whether `total` met the target
+    #[serde(default)]
+    pub passed: Option<bool>,
+    // The band of the interpreter the roll was read with that it fell in
+    #[serde(default)]
+    pub outcome: Option<Band>,
+    // Critical range each die in `dice` landed in, for dice that counted; empty when none did
+    #[serde(default)]
+    pub crits: Vec<Option<Crit>>,
+    // Whether each die in `dice` was added by `crit_rule`; empty when none was
+    #[serde(default)]
+    pub crit_extra: Vec<bool>,
+    // Crit damage rule applied to the roll
+    #[serde(default)]
+    pub crit_rule: Option<CritRule>,
+    // Named groups of a grouped roll, whose totals add up to `total`; empty otherwise
+    #[serde(default)]
+    pub groups: Vec<RollGroup>,
+    // How many times `expression` was rolled, its groups repeated once per repetition
+    #[serde(default = "default_repeats")]
+    pub repeats: u32,
+    // Scores assigned to each ability, for arrays saved from the ability score dialog
+    #[serde(default)]
+    pub abilities: Vec<(String, i32)>,
+    // Seed the dice were drawn from, when every one of them was seeded
+    #[serde(default)]
+    pub seed: Option<u64>,
+    // Index of the draw behind each die in `dice`, followed by those of the extra
+    // dice of its explosion; empty unless seeded. A die's face and any rerolls come
+    // from `seed` and its index alone
+    #[serde(default)]
+    pub sequence: Vec<Vec<u64>>,
+}
+
+fn default_repeats() -> u32 {
+    1
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct RollGroup {
+    // None for dice added to the tray outside any named group
+    pub name: Option<String>,
+    // Indices into the entry's `dice`
+    pub dice: Vec<usize>,
+    pub modifier: i32,
+    #[serde(default)]
+    pub total: i32,
+}
+
+// The state of the tray at the moment it is recorded
+#[derive(Clone, Default)]
+pub struct RollSnapshot {
+    pub dice: Vec<(DieKind, u32)>,
+    pub kept: Vec<bool>,
+    pub keep: Option<Keep>,
+    pub advantage: Vec<Option<Advantage>>,
+    pub negative: Vec<bool>,
+    pub crits: Vec<Option<Crit>>,
+    pub crit_extra: Vec<bool>,
+    pub crit_rule: Option<CritRule>,
+    pub chains: Vec<Option<Vec<u32>>>,
+    pub rerolls: Vec<Vec<u32>>,
+    pub reroll_rule: Option<Reroll>,
+    pub modifier: i32,
+    pub expression: Option<String>,
+    pub pool: Option<Pool>,
+    pub target: Option<Target>,
+    pub interpreter: Option<Interpreter>,
+    pub successes: Option<i32>,
+    pub groups: Vec<RollGroup>,
+    pub repeats: u32,
+    pub abilities: Vec<(String, i32)>,
+    pub seed: Option<u64>,
+    pub sequence: Vec<Vec<u64>>,
+}
+
+// Formats a modifier for display next to the dice, e.g. "+3" or "-2"
+pub fn format_modifier(modifier: i32) -> String {
+    format!("{:+}", modifier)
+}
+
+pub fn format_successes(successes: i32) -> String {
+    if successes == 1 {
+        "1 success".to_string()
+    } else {
+        format!("{} successes", successes)
+    }
 }
 
 pub struct RollHistory {
@@ -28,12 +149,73 @@ impl RollHistory {
         }
     }
 
-    pub fn add_recent(&mut self, dice: Vec<(DieKind, u32)>) -> RollEntry {
-        let total = dice.iter().map(|(_, v)| v).sum();
+    pub fn add_recent(&mut self, snapshot: RollSnapshot) -> RollEntry {
+        // What the die at `i` adds to the total, including the extra dice of its chain
+        let die_value = |i: usize| -> i32 {
+            if !snapshot.kept.get(i).copied().unwrap_or(true) {
+                return 0;
+            }
+            let (kind, v) = snapshot.dice[i];
+            let extras: i32 = snapshot.chains.get(i).cloned().flatten().unwrap_or_default()
+                .iter()
+                .map(|&e| kind.value(e))
+                .sum();
+            let sign = if snapshot.negative.get(i).copied().unwrap_or(false) { -1 } else { 1 };
+            sign * (kind.value(v) + extras)
+        };
+        // Doubling the total on a crit doubles every group's share of it too
+        let factor = if snapshot.crit_rule == Some(CritRule::DoubleTotal) { 2 } else { 1 };
+        let sum: i32 = (0..snapshot.dice.len()).map(die_value).sum();
+        let groups: Vec<RollGroup> = snapshot.groups.iter()
+            .map(|group| RollGroup {
+                total: (group.dice.iter().map(|&i| die_value(i)).sum::<i32>() + group.modifier) * factor,
+                ..group.clone()
+            })
+            .collect();
+        let total = match snapshot.successes {
+            Some(successes) => successes,
+            None if !groups.is_empty() => groups.iter().map(|g| g.total).sum(),
+            None => (sum + snapshot.modifier) * factor,
+        };
+        let values: Vec<i32> = snapshot.dice.iter()
+            .enumerate()
+            .filter(|&(i, _)| snapshot.kept.get(i).copied().unwrap_or(true))
+            .map(|(i, &(kind, v))| if snapshot.negative.get(i).copied().unwrap_or(false) { -kind.value(v) } else { kind.value(v) })
+            .collect();
+        let outcome = snapshot.interpreter.as_ref().and_then(|interpreter| interpreter.read(&values, total)).cloned();
+        let kept = if snapshot.kept.iter().all(|&k| k) { Vec::new() } else { snapshot.kept };
+        let advantage = if snapshot.advantage.iter().all(Option::is_none) { Vec::new() } else { snapshot.advantage };
+        let negative = if snapshot.negative.iter().all(|&n| !n) { Vec::new() } else { snapshot.negative };
+        let chains = if snapshot.chains.iter().all(Option::is_none) { Vec::new() } else { snapshot.chains };
+        let crits = if snapshot.crits.iter().all(Option::is_none) { Vec::new() } else { snapshot.crits };
+        let crit_extra = if snapshot.crit_extra.iter().all(|&extra| !extra) { Vec::new() } else { snapshot.crit_extra };
+        let rerolls = if snapshot.rerolls.iter().all(Vec::is_empty) { Vec::new() } else { snapshot.rerolls };
         let entry = RollEntry {
             id: self.next_id,
-            dice,
+            dice: snapshot.dice,
             total,
+            kept,
+            keep: snapshot.keep,
+            advantage,
+            negative,
+            crits,
+            crit_extra,
+            crit_rule: snapshot.crit_rule,
+            chains,
+            rerolls,
+            reroll_rule: snapshot.reroll_rule,
+            modifier: snapshot.modifier,
+            expression: snapshot.expression,
+            pool: snapshot.pool,
+            successes: snapshot.successes,
+            target: snapshot.target,
+            passed: snapshot.target.map(|target| target.passes(total)),
+            outcome,
+            groups,
+            repeats: snapshot.repeats.max(1),
+            abilities: snapshot.abilities,
+            seed: snapshot.seed,
+            sequence: snapshot.sequence,
         };
         self.next_id += 1;
         self.recents.insert(0, entry.clone());
@@ -83,19 +265,283 @@ impl RollHistory {
     }
 
     pub fn format_roll(entry: &RollEntry) -> (String, String) {
-        let mut counts: BTreeMap<&str, u32> = BTreeMap::new();
-        for (kind, _) in &entry.dice {
+        // Ability arrays read "Ability Scores (4d6dl1)" over "STR 15 · DEX 14 · …"
+        if !entry.abilities.is_empty() {
+            let title = match entry.expression {
+                Some(ref expression) => format!("Ability Scores ({})", glib::markup_escape_text(expression)),
+                None => "Ability Scores".to_string(),
+            };
+            let scores: Vec<String> = entry.abilities.iter()
+                .map(|(ability, score)| format!("{} {}", glib::markup_escape_text(ability), score))
+                .collect();
+            return (title, scores.join(" · "));
+        }
+
+        let result = match entry.successes {
+            Some(successes) => format!("= {}", format_successes(successes)),
+            // Repeated rolls list the total of each repetition, e.g. "= 14 · 12 · 9"
+            None if entry.repeats > 1 => {
+                let totals: Vec<String> = Self::repetitions(entry).iter()
+                    .map(|groups| groups.iter().map(|g| g.total).sum::<i32>().to_string())
+                    .collect();
+                format!("= {}", totals.join(" · "))
+            }
+            // Grouped rolls list each group's total, e.g. "= To hit 17 · Damage 9"
+            None if !entry.groups.is_empty() => {
+                let totals: Vec<String> = entry.groups.iter()
+                    .map(|group| match &group.name {
+                        Some(name) => format!("{} {}", glib::markup_escape_text(name), group.total),
+                        None => group.total.to_string(),
+                    })
+                    .collect();
+                format!("= {}", totals.join(" · "))
+            }
+            None => {
+                let symbolic = entry.modifier == 0
+                    && entry.dice.iter().all(|&(kind, _)| custom_dice::is_symbolic(kind));
+                let total = custom_dice::format_total(entry.total, Self::format_tally(entry), symbolic);
+                format!("= {}", glib::markup_escape_text(&total))
+            }
+        };
+        // Rolls against a target read "17 ✔ (+2)"
+        let result = match (entry.target, entry.passed) {
+            (Some(target), Some(passed)) => format!(
+                "{} {} ({})",
+                result,
+                if passed { "✔" } else { "✘" },
+                format_modifier(target.margin(entry.total)),
+            ),
+            _ => result,
+        };
+        let details: Vec<String> = [Self::format_labels(entry), Self::format_chains(entry), Self::format_rerolls(entry)]
+            .into_iter()
+            .flatten()
+            .collect();
+        let subtitle = if details.is_empty() {
+            result
+        } else {
+            format!("{} ({})", result, details.join("; "))
+        };
+        let mut suffix = [entry.pool.map(|pool| pool.target.to_string()), entry.target.map(|target| target.to_string())]
+            .into_iter()
+            .flatten()
+            .map(|target| format!(" vs {}", target))
+            .collect::<String>();
+        if let Some(rule) = entry.crit_rule {
+            suffix.push_str(&format!(" · Crit ({})", rule.label()));
+        }
+        if let Some(ref expression) = entry.expression {
+            // Group names are user text
+            let repeats = if entry.repeats > 1 { format!(" ×{}", entry.repeats) } else { String::new() };
+            return (format!("{}{}{}", glib::markup_escape_text(expression), repeats, suffix), subtitle);
+        }
+
+        // Dice taken away from the total sort after the ones added, e.g. "1d20 - 1d4"
+        let mut counts: BTreeMap<(bool, String), u32> = BTreeMap::new();
+        for (i, (kind, _)) in entry.dice.iter().enumerate() {
+            let advantage = entry.advantage.get(i).copied().flatten();
+            // An advantage pair is listed once, by the die that counted
+            if advantage.is_some() && !entry.kept.get(i).copied().unwrap_or(true) {
+                continue;
+            }
             let name = match kind {
-                DieKind::Four => "d4",
-                DieKind::Six => "d6",
-                DieKind::Eight => "d8",
-                DieKind::Ten => "d10",
-                DieKind::Twelve => "d12",
-                DieKind::Twenty => "d20",
+                // Named dice read "2 Boost" rather than "2d6"
+                DieKind::Defined(_) => format!(" {}", glib::markup_escape_text(&kind.name())),
+                _ => kind.name(),
+            };
+            let name = match advantage {
+                Some(advantage) => format!("{} ({})", name, advantage.abbreviation()),
+                None if entry.chains.get(i).is_some_and(Option::is_some) => format!("{}!", name),
+                None => name,
             };
-            *counts.entry(name).or_insert(0) += 1;
+            let negative = entry.negative.get(i).copied().unwrap_or(false);
+            *counts.entry((negative, name)).or_insert(0) += 1;
+        }
+        let mut title = String::new();
+        for (i, ((negative, k), v)) in counts.iter().enumerate() {
+            match (i, negative) {
+                (0, true) => title.push('-'),
+                (0, false) => {}
+                (_, true) => title.push_str(" - "),
+                (_, false) => title.push_str(" + "),
+            }
+            match (k.ends_with(')'), v) {
+                (true, 1) => title.push_str(k),
+                _ => title.push_str(&format!("{}{}", v, k)),
+            }
+        }
+        if let Some(rule) = entry.reroll_rule {
+            title.push_str(&format!(" {}", rule));
         }
-        let title: Vec<String> = counts.iter().map(|(k, v)| format!("{}{}", v, k)).collect();
-        (title.join(" + "), format!("= {}", entry.total))
+        if let Some(keep) = entry.keep {
+            title.push_str(&format!(" {}", keep));
+        }
+        if entry.modifier != 0 {
+            let sign = if entry.modifier < 0 { '-' } else { '+' };
+            title.push_str(&format!(" {} {}", sign, entry.modifier.abs()));
+        }
+        title.push_str(&suffix);
+        (title, subtitle)
+    }
+
+    // Where a seeded roll can be found again, e.g. "Seed 1234, draws 17–21"
+    pub fn format_sequence(entry: &RollEntry) -> Option<String> {
+        let seed = entry.seed?;
+        let mut draws: Vec<u64> = entry.sequence.iter().flatten().copied().collect();
+        draws.sort_unstable();
+        let draws = match (draws.first(), draws.last()) {
+            (Some(first), Some(last)) if first == last => format!("draw {}", first),
+            (Some(first), Some(last)) if last - first + 1 == draws.len() as u64 => format!("draws {}–{}", first, last),
+            _ => format!("draws {}", draws.iter().map(u64::to_string).collect::<Vec<String>>().join(", ")),
+        };
+        Some(format!("Seed {}, {}", seed, draws))
+    }
+
+    // The symbols on every face that counted, from user-defined dice
+    fn format_tally(entry: &RollEntry) -> Option<String> {
+        let faces: Vec<(DieKind, u32)> = entry.dice.iter()
+            .enumerate()
+            .filter(|&(i, _)| entry.kept.get(i).copied().unwrap_or(true))
+            .flat_map(|(i, &(kind, val))| {
+                let extras = entry.chains.get(i).cloned().flatten().unwrap_or_default();
+                std::iter::once((kind, val)).chain(extras.into_iter().map(move |e| (kind, e)))
+            })
+            .collect();
+        let symbols: Vec<String> = faces.iter()
+            .flat_map(|&(kind, face)| custom_dice::symbols(kind, face))
+            .collect();
+        custom_dice::format_tally(symbols.iter().map(String::as_str))
+    }
+
+    // The result of each repetition of a repeated roll, e.g. "= 17 (To hit 12 · Damage 5)"
+    pub fn format_repeats(entry: &RollEntry) -> Vec<String> {
+        Self::repetitions(entry).into_iter()
+            .map(|chunk| {
+                let total: i32 = chunk.iter().map(|g| g.total).sum();
+                if chunk.len() == 1 {
+                    return format!("= {}", total);
+                }
+                let parts: Vec<String> = chunk.iter()
+                    .map(|g| {
+                        let name = g.name.as_deref().unwrap_or_default();
+                        let name = name.rsplit_once(" #").map_or(name, |(name, _)| name);
+                        format!("{} {}", glib::markup_escape_text(name), g.total)
+                    })
+                    .collect();
+                format!("= {} ({})", total, parts.join(" · "))
+            })
+            .collect()
+    }
+
+    // The named groups of each repetition, leaving out dice added to the tray afterwards
+    fn repetitions(entry: &RollEntry) -> Vec<Vec<&RollGroup>> {
+        let groups: Vec<&RollGroup> = entry.groups.iter().filter(|g| g.name.is_some()).collect();
+        let repeats = entry.repeats.max(1) as usize;
+        if groups.is_empty() || !groups.len().is_multiple_of(repeats) {
+            return Vec::new();
+        }
+        groups.chunks(groups.len() / repeats).map(<[_]>::to_vec).collect()
+    }
+
+    // Lists the faces user-defined dice landed on, e.g. "S, SA, A"
+    fn format_labels(entry: &RollEntry) -> Option<String> {
+        let labels: Vec<String> = entry.dice.iter()
+            .filter_map(|&(kind, val)| match kind {
+                DieKind::Defined(id) => custom_dice::with(id, |def| def.face(val).map(|f| f.label.clone())).flatten(),
+                _ => None,
+            })
+            .filter(|label| !label.is_empty())
+            .map(|label| glib::markup_escape_text(&label).to_string())
+            .collect();
+        (!labels.is_empty()).then(|| labels.join(", "))
+    }
+
+    // Lists every die that exploded with the rolls of its chain, e.g. "6→6→3, 4→1"
+    fn format_chains(entry: &RollEntry) -> Option<String> {
+        let chains: Vec<String> = entry.dice.iter()
+            .zip(&entry.chains)
+            .filter_map(|(&(kind, val), chain)| match chain {
+                Some(extras) if !extras.is_empty() => Some(
+                    std::iter::once(&val).chain(extras)
+                        .map(|&face| kind.value(face).to_string())
+                        .collect::<Vec<String>>()
+                        .join("→"),
+                ),
+                _ => None,
+            })
+            .collect();
+        (!chains.is_empty()).then(|| chains.join(", "))
+    }
+
+    // Lists every rerolled die as markup with the values it replaced struck through,
+    // e.g. "rerolled <s>2</s> 5"
+    fn format_rerolls(entry: &RollEntry) -> Option<String> {
+        let rerolls: Vec<String> = entry.dice.iter()
+            .zip(&entry.rerolls)
+            .filter(|(_, replaced)| !replaced.is_empty())
+            .map(|(&(kind, val), replaced)| {
+                replaced.iter()
+                    .map(|&face| format!("<s>{}</s>", kind.value(face)))
+                    .chain(std::iter::once(kind.value(val).to_string()))
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            })
+            .collect();
+        (!rerolls.is_empty()).then(|| format!("rerolled {}", rerolls.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> RollHistory {
+        RollHistory { recents: Vec::new(), favorites: Vec::new(), next_id: 1 }
+    }
+
+    // 1d20 - 1d4 + 2, rolling 15 and 3
+    fn subtracted() -> RollSnapshot {
+        RollSnapshot {
+            dice: vec![(DieKind::Twenty, 15), (DieKind::Four, 3)],
+            kept: vec![true, true],
+            negative: vec![false, true],
+            modifier: 2,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn subtracted_dice_count_against_the_total() {
+        let entry = history().add_recent(subtracted());
+        assert_eq!(entry.total, 14);
+        assert_eq!(entry.negative, vec![false, true]);
+        assert_eq!(RollHistory::format_roll(&entry).0, "1d20 - 1d4 + 2");
+    }
+
+    #[test]
+    fn subtracted_dice_count_against_their_group() {
+        let entry = history().add_recent(RollSnapshot {
+            groups: vec![RollGroup { name: Some("Damage".to_string()), dice: vec![0, 1], modifier: 2, total: 0 }],
+            ..subtracted()
+        });
+        assert_eq!(entry.groups[0].total, 14);
+        assert_eq!(entry.total, 14);
+    }
+
+    #[test]
+    fn seeded_rolls_list_their_draws() {
+        let mut history = history();
+        let entry = history.add_recent(RollSnapshot { seed: Some(42), sequence: vec![vec![17], vec![18, 19]], ..subtracted() });
+        assert_eq!(RollHistory::format_sequence(&entry).as_deref(), Some("Seed 42, draws 17–19"));
+        let entry = history.add_recent(RollSnapshot { seed: Some(42), sequence: vec![vec![17], vec![23]], ..subtracted() });
+        assert_eq!(RollHistory::format_sequence(&entry).as_deref(), Some("Seed 42, draws 17, 23"));
+        assert!(RollHistory::format_sequence(&history.add_recent(subtracted())).is_none());
+    }
+
+    #[test]
+    fn added_dice_leave_no_negative_flags() {
+        let entry = history().add_recent(RollSnapshot { negative: vec![false, false], ..subtracted() });
+        assert_eq!(entry.total, 20);
+        assert!(entry.negative.is_empty());
     }
 }
@@ -3,15 +3,18 @@ use adw::prelude::*;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::die::DieKind;
-use crate::roll_history::{RollEntry, RollHistory};
+use crate::die::Crit;
+use crate::outcomes;
+use crate::roll_history::{RollEntry, RollHistory, RollSnapshot};
+
+type RestoreFn = dyn Fn(&RollEntry);
 
 pub struct Sidebar {
     widget: gtk::Box,
     recents_listbox: gtk::ListBox,
     favorites_listbox: gtk::ListBox,
     history: Rc<RefCell<RollHistory>>,
-    on_restore: Rc<dyn Fn(&[(DieKind, u32)])>,
+    on_restore: Rc<RestoreFn>,
 }
 
 impl std::fmt::Debug for Sidebar {
@@ -21,7 +24,7 @@ impl std::fmt::Debug for Sidebar {
 }
 
 impl Sidebar {
-    pub fn new(on_restore: impl Fn(&[(DieKind, u32)]) + 'static) -> Rc<RefCell<Self>> {
+    pub fn new(on_restore: impl Fn(&RollEntry) + 'static) -> Rc<RefCell<Self>> {
         let history = Rc::new(RefCell::new(RollHistory::new()));
         let on_restore = Rc::new(on_restore);
 
@@ -102,9 +105,9 @@ impl Sidebar {
         sidebar
     }
 
-    pub fn add_recent(&self, dice: Vec<(DieKind, u32)>, sidebar_rc: &Rc<RefCell<Self>>) {
-        if dice.is_empty() { return; }
-        let entry = self.history.borrow_mut().add_recent(dice);
+    pub fn add_recent(&self, snapshot: RollSnapshot, sidebar_rc: &Rc<RefCell<Self>>) {
+        if snapshot.dice.is_empty() && snapshot.abilities.is_empty() { return; }
+        let entry = self.history.borrow_mut().add_recent(snapshot);
         let row = self.build_recent_row(&entry, sidebar_rc);
         self.recents_listbox.prepend(&row);
     }
@@ -113,14 +116,7 @@ impl Sidebar {
         &self.widget
     }
 
-    fn build_recent_row(&self, entry: &RollEntry, sidebar_rc: &Rc<RefCell<Self>>) -> adw::ActionRow {
-        let (title, subtitle) = RollHistory::format_roll(entry);
-        let row = adw::ActionRow::builder()
-            .title(&title)
-            .subtitle(&subtitle)
-            .activatable(true)
-            .build();
-
+    fn build_recent_row(&self, entry: &RollEntry, sidebar_rc: &Rc<RefCell<Self>>) -> gtk::Widget {
         let star_button = gtk::Button::builder()
             .icon_name("starred-symbolic")
             .valign(gtk::Align::Center)
@@ -137,25 +133,11 @@ impl Sidebar {
                 s.refresh_favorites(&sidebar_rc);
             }
         });
-        row.add_suffix(&star_button);
 
-        let dice = entry.dice.clone();
-        let restore = self.on_restore.clone();
-        row.connect_activated(move |_| {
-            restore(&dice);
-        });
-
-        row
+        build_entry_row(entry, &star_button, self.on_restore.clone())
     }
 
-    fn build_favorite_row_static(entry: &RollEntry, sidebar_rc: &Rc<RefCell<Self>>) -> adw::ActionRow {
-        let (title, subtitle) = RollHistory::format_roll(entry);
-        let row = adw::ActionRow::builder()
-            .title(&title)
-            .subtitle(&subtitle)
-            .activatable(true)
-            .build();
-
+    fn build_favorite_row_static(entry: &RollEntry, sidebar_rc: &Rc<RefCell<Self>>) -> gtk::Widget {
         let remove_button = gtk::Button::builder()
             .icon_name("user-trash-symbolic")
             .valign(gtk::Align::Center)
@@ -172,18 +154,12 @@ impl Sidebar {
                 s.refresh_favorites(&sidebar_rc);
             }
         });
-        row.add_suffix(&remove_button);
 
-        let dice = entry.dice.clone();
         let restore = {
             let s = sidebar_rc.borrow();
             s.on_restore.clone()
         };
-        row.connect_activated(move |_| {
-            restore(&dice);
-        });
-
-        row
+        build_entry_row(entry, &remove_button, restore)
     }
 
     fn refresh_favorites(&self, sidebar_rc: &Rc<RefCell<Self>>) {
@@ -199,3 +175,94 @@ impl Sidebar {
         }
     }
 }
+
+// A row that rolls the entry again when activated. Repeated rolls expand to list
+// each repetition instead, with a button to roll them again.
+fn build_entry_row(entry: &RollEntry, suffix: &gtk::Button, restore: Rc<RestoreFn>) -> gtk::Widget {
+    let (title, subtitle) = RollHistory::format_roll(entry);
+    let sequence = RollHistory::format_sequence(entry);
+    let restored = entry.clone();
+
+    if entry.repeats > 1 {
+        let row = adw::ExpanderRow::builder()
+            .title(&title)
+            .subtitle(&subtitle)
+            .build();
+        row.set_tooltip_text(sequence.as_deref());
+        for badge in crit_badges(entry) {
+            row.add_suffix(&badge);
+        }
+        if let Some(badge) = outcome_badge(entry) {
+            row.add_suffix(&badge);
+        }
+
+        let again_button = gtk::Button::builder()
+            .icon_name("view-refresh-symbolic")
+            .valign(gtk::Align::Center)
+            .css_classes(vec!["flat"])
+            .tooltip_text("Roll again")
+            .build();
+        again_button.connect_clicked(move |_| {
+            restore(&restored);
+        });
+        row.add_suffix(&again_button);
+        row.add_suffix(suffix);
+
+        for (k, subtitle) in RollHistory::format_repeats(entry).into_iter().enumerate() {
+            let child = adw::ActionRow::builder()
+                .title(format!("#{}", k + 1))
+                .subtitle(&subtitle)
+                .build();
+            row.add_row(&child);
+        }
+        return row.upcast();
+    }
+
+    let row = adw::ActionRow::builder()
+        .title(&title)
+        .subtitle(&subtitle)
+        .activatable(true)
+        .build();
+    row.set_tooltip_text(sequence.as_deref());
+    for badge in crit_badges(entry) {
+        row.add_suffix(&badge);
+    }
+    if let Some(badge) = outcome_badge(entry) {
+        row.add_suffix(&badge);
+    }
+    row.add_suffix(suffix);
+    row.connect_activated(move |_| {
+        restore(&restored);
+    });
+    row.upcast()
+}
+
+// Marks rows whose roll had a die land in its critical range
+fn crit_badges(entry: &RollEntry) -> Vec<gtk::Label> {
+    [
+        (Crit::Success, "Crit", "Critical success", "success"),
+        (Crit::Failure, "Fumble", "Critical failure", "error"),
+    ]
+    .into_iter()
+    .filter(|&(crit, ..)| entry.crits.contains(&Some(crit)))
+    .map(|(_, text, tooltip, class)| {
+        gtk::Label::builder()
+            .label(text)
+            .tooltip_text(tooltip)
+            .valign(gtk::Align::Center)
+            .css_classes(vec!["caption-heading", class])
+            .build()
+    })
+    .collect()
+}
+
+// The outcome the roll was read as, in its color
+fn outcome_badge(entry: &RollEntry) -> Option<gtk::Label> {
+    let outcome = entry.outcome.as_ref()?;
+    Some(gtk::Label::builder()
+        .label(outcomes::markup(outcome))
+        .use_markup(true)
+        .valign(gtk::Align::Center)
+        .css_classes(vec!["caption"])
+        .build())
+}
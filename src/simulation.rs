@@ -0,0 +1,272 @@
+// Estimates the odds of a roll by rolling it over and over on a worker thread,
+// for rolls with too many ways to come out to work out exactly. Dice are rolled
+// with the generator picked in Preferences, so the estimate reflects it too.
+
+use gtk::glib;
+use rand::Rng;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use crate::dice_area::MAX_EXPLODE_DEPTH;
+use crate::die::{self, Keep, Reroll};
+use crate::probability::{Distribution, RollDefinition};
+
+pub const TRIALS: u64 = 1_000_000;
+
+// Rolls made between progress updates
+const BATCH: u64 = 20_000;
+
+// z for a 95% confidence interval
+const Z: f64 = 1.96;
+
+// A die with everything the worker thread needs to know copied out of its kind,
+// since custom dice are only known on the main thread
+struct SimDie {
+    // What each face adds to the total, from face 1 up
+    values: Vec<i32>,
+    // The face an exploding die rolls again on, its kind's highest
+    explode_face: Option<u32>,
+    reroll: Option<Reroll>,
+    fixed: Option<u32>,
+}
+
+impl SimDie {
+    // Rolls the die and any dice its explosion spawns, returning their faces added up and value
+    fn roll(&self, rng: &mut dyn rand::RngCore) -> (u32, i32) {
+        let sides = self.values.len() as u32;
+        if let Some(face) = self.fixed {
+            return (face, self.values[face as usize - 1]);
+        }
+
+        let (mut key, mut val) = (0, 0);
+        for depth in 0..=MAX_EXPLODE_DEPTH {
+            let first = rng.gen_range(1..=sides);
+            let face = match self.reroll {
+                Some(rule) => rule.follow_ups(sides, first, || rng.gen_range(1..=sides)).back().copied().unwrap_or(first),
+                None => first,
+            };
+            key += face;
+            val += self.values[face as usize - 1];
+            if !(self.explode_face == Some(face) && depth < MAX_EXPLODE_DEPTH) {
+                break;
+            }
+        }
+        (key, val)
+    }
+}
+
+struct SimSet {
+    sign: i32,
+    dice: Vec<SimDie>,
+    keep: Option<Keep>,
+}
+
+// A roll definition that can be sent to the worker thread
+pub struct Simulation {
+    sets: Vec<SimSet>,
+    modifier: i32,
+    double: bool,
+}
+
+impl Simulation {
+    pub fn new(def: &RollDefinition) -> Self {
+        let sets = def.sets.iter()
+            .map(|set| SimSet {
+                sign: set.sign,
+                dice: set.dice.iter()
+                    .map(|spec| SimDie {
+                        values: spec.kind.faces()
+                            .map(|face| def.pool.map_or_else(|| spec.kind.value(face), |pool| pool.successes(face)))
+                            .collect(),
+                        explode_face: spec.explode.then(|| spec.kind.max_face()),
+                        reroll: spec.reroll,
+                        fixed: spec.fixed.then(|| spec.kind.max_face()),
+                    })
+                    .collect(),
+                keep: set.keep,
+            })
+            .collect();
+        Self { sets, modifier: def.modifier, double: def.double && def.pool.is_none() }
+    }
+
+    fn roll(&self, rng: &mut dyn rand::RngCore) -> i32 {
+        let mut total = self.modifier;
+        for set in &self.sets {
+            let rolls: Vec<(u32, i32)> = set.dice.iter().map(|d| d.roll(rng)).collect();
+            let sum: i32 = match set.keep {
+                Some(keep) => {
+                    let keys: Vec<u32> = rolls.iter().map(|&(key, _)| key).collect();
+                    rolls.iter().zip(keep.apply(&keys)).filter(|&(_, kept)| kept).map(|(&(_, val), _)| val).sum()
+                }
+                None => rolls.iter().map(|&(_, val)| val).sum(),
+            };
+            total += set.sign * sum;
+        }
+        if self.double { total * 2 } else { total }
+    }
+}
+
+// The totals rolled so far
+#[derive(Clone)]
+pub struct Estimate {
+    pub trials: u64,
+    pub counts: BTreeMap<i32, u64>,
+}
+
+impl Estimate {
+    pub fn distribution(&self) -> Distribution {
+        Distribution::from_counts(&self.counts)
+    }
+}
+
+// Half the width of the 95% confidence interval of the mean
+pub fn mean_margin(dist: &Distribution, trials: u64) -> f64 {
+    Z * dist.std_dev() / (trials.max(1) as f64).sqrt()
+}
+
+// The 95% confidence interval of a chance seen in `trials` rolls, by the Wilson score
+pub fn chance_interval(p: f64, trials: u64) -> (f64, f64) {
+    let n = trials.max(1) as f64;
+    let denominator = 1.0 + Z * Z / n;
+    let center = (p + Z * Z / (2.0 * n)) / denominator;
+    let margin = Z * (p * (1.0 - p) / n + Z * Z / (4.0 * n * n)).sqrt() / denominator;
+    ((center - margin).max(0.0), (center + margin).min(1.0))
+}
+
+// Stops a running simulation when cancelled or dropped
+pub struct Handle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Handle {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// Rolls `sim` `trials` times off the main thread, calling `on_update` on the main
+// thread as the estimate grows and `on_done` once every roll is in
+pub fn start(
+    sim: Simulation,
+    trials: u64,
+    on_update: impl Fn(&Estimate) + 'static,
+    on_done: impl FnOnce(Estimate) + 'static,
+) -> Handle {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel::<Estimate>();
+    let algorithm = die::rng_algorithm();
+
+    let cancelled_clone = cancelled.clone();
+    thread::spawn(move || {
+        let mut rng = die::rng_named(&algorithm);
+        let mut estimate = Estimate { trials: 0, counts: BTreeMap::new() };
+        while estimate.trials < trials && !cancelled_clone.load(Ordering::Relaxed) {
+            let batch = BATCH.min(trials - estimate.trials);
+            for _ in 0..batch {
+                *estimate.counts.entry(sim.roll(&mut *rng)).or_insert(0) += 1;
+            }
+            estimate.trials += batch;
+            if sender.send(estimate.clone()).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Only the latest estimate is shown; the channel closes when the thread stops
+    let mut on_done = Some(on_done);
+    let mut latest: Option<Estimate> = None;
+    glib::timeout_add_local(Duration::from_millis(100), move || {
+        let mut updated = false;
+        let stopped = loop {
+            match receiver.try_recv() {
+                Ok(estimate) => {
+                    latest = Some(estimate);
+                    updated = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => break false,
+                Err(mpsc::TryRecvError::Disconnected) => break true,
+            }
+        };
+
+        if !stopped {
+            if let (true, Some(estimate)) = (updated, &latest) {
+                on_update(estimate);
+            }
+            return glib::ControlFlow::Continue;
+        }
+        // A cancelled run stops short of `trials` and reports nothing
+        if let (Some(estimate), Some(on_done)) = (latest.take(), on_done.take()) {
+            if estimate.trials >= trials {
+                on_done(estimate);
+            }
+        }
+        glib::ControlFlow::Break
+    });
+
+    Handle { cancelled }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    // Simulates the notation with a fixed seed and checks every total against its exact odds
+    fn assert_matches_exact(text: &str) {
+        const ROLLS: u64 = 200_000;
+        let def = RollDefinition::from_roll(&notation::parse(text).ok().unwrap());
+        let exact = def.distribution().unwrap();
+
+        let sim = Simulation::new(&def);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = BTreeMap::new();
+        for _ in 0..ROLLS {
+            *counts.entry(sim.roll(&mut rng)).or_insert(0) += 1;
+        }
+        let simulated = Estimate { trials: ROLLS, counts }.distribution();
+
+        assert!((simulated.mean() - exact.mean()).abs() < 2.0 * mean_margin(&exact, ROLLS), "{}: mean {} vs {}", text, simulated.mean(), exact.mean());
+        for (&total, &p) in exact.odds() {
+            let q = simulated.odds().get(&total).copied().unwrap_or(0.0);
+            // Five standard errors, plus room for totals too rare to turn up at all
+            let allowed = 5.0 * (p * (1.0 - p) / ROLLS as f64).sqrt() + 1e-4;
+            assert!((p - q).abs() <= allowed, "{}: total {} came up {} of the time, expected {}", text, total, q, p);
+        }
+        assert!(simulated.odds().keys().all(|total| exact.odds().contains_key(total)), "{}: impossible total rolled", text);
+    }
+
+    #[test]
+    fn exploding_dice_match_exact_odds() {
+        assert_matches_exact("3d6!");
+    }
+
+    #[test]
+    fn kept_dice_match_exact_odds() {
+        assert_matches_exact("4d6kh3");
+    }
+
+    #[test]
+    fn chance_interval_is_the_wilson_score() {
+        // 50 successes in 100 trials
+        let (low, high) = chance_interval(0.5, 100);
+        assert!((low - 0.4038).abs() < 1e-4 && (high - 0.5962).abs() < 1e-4);
+
+        // Never seen in 10 trials still leaves room above zero
+        let (low, high) = chance_interval(0.0, 10);
+        assert_eq!(low, 0.0);
+        assert!((high - 0.2775).abs() < 1e-4);
+
+        let (low, high) = chance_interval(1.0, 0);
+        assert!((0.0..=1.0).contains(&low) && high == 1.0);
+    }
+}
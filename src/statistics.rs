@@ -0,0 +1,238 @@
+// How every die has rolled, over its lifetime and in each session the app was
+// open for. Every face a die settles on is counted here, including faces a
+// reroll replaced. The counts live in statistics.json next to favorites.json.
+
+use gtk::glib;
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::die::DieKind;
+
+// Older sessions are forgotten past this many
+const MAX_SESSIONS: usize = 50;
+
+// Seconds to wait after dice land before writing the counts out, so a tray of
+// dice settling over several frames is saved once and never from the draw callback
+const SAVE_DELAY: u32 = 2;
+
+// How often each face of one kind of die came up
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Tally {
+    pub kind: DieKind,
+    // Indexed by face, from face 1
+    pub counts: Vec<u64>,
+    // Rolls in a row above the die's average when positive, below it when negative
+    #[serde(default)]
+    pub streak: i64,
+    #[serde(default)]
+    pub longest_high: u64,
+    #[serde(default)]
+    pub longest_low: u64,
+}
+
+impl Tally {
+    fn new(kind: DieKind) -> Self {
+        Self { kind, counts: Vec::new(), streak: 0, longest_high: 0, longest_low: 0 }
+    }
+
+    fn record(&mut self, face: u32) {
+        let sides = self.kind.faces().count().max(face as usize);
+        if self.counts.len() < sides {
+            self.counts.resize(sides, 0);
+        }
+        self.counts[face as usize - 1] += 1;
+
+        let value = self.kind.value(face) as f64;
+        let expected = self.expected();
+        self.streak = match self.streak {
+            _ if value == expected => 0,
+            streak if value > expected => streak.max(0) + 1,
+            streak => streak.min(0) - 1,
+        };
+        self.longest_high = self.longest_high.max(self.streak.max(0) as u64);
+        self.longest_low = self.longest_low.max(self.streak.min(0).unsigned_abs());
+    }
+
+    pub fn rolls(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn face_values(&self) -> impl Iterator<Item = f64> + '_ {
+        self.kind.faces().map(|face| self.kind.value(face) as f64)
+    }
+
+    pub fn average(&self) -> f64 {
+        let sum: f64 = self.counts.iter()
+            .enumerate()
+            .map(|(i, &count)| self.kind.value(i as u32 + 1) as f64 * count as f64)
+            .sum();
+        sum / self.rolls().max(1) as f64
+    }
+
+    // What a fair die averages
+    pub fn expected(&self) -> f64 {
+        let sides = self.kind.faces().count().max(1) as f64;
+        self.face_values().sum::<f64>() / sides
+    }
+
+    fn variance(&self) -> f64 {
+        let expected = self.expected();
+        let sides = self.kind.faces().count().max(1) as f64;
+        self.face_values().map(|value| (value - expected).powi(2)).sum::<f64>() / sides
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Session {
+    // Unix time the session rolled its first die
+    pub started: i64,
+    pub tallies: Vec<Tally>,
+}
+
+impl Session {
+    pub fn rolls(&self) -> u64 {
+        self.tallies.iter().map(Tally::rolls).sum()
+    }
+
+    // How far above or below a fair result the session's rolls came out, in
+    // standard deviations of their sum; 0 is exactly average
+    pub fn luck(&self) -> f64 {
+        let (deviation, variance) = self.tallies.iter()
+            .filter(|tally| tally.variance() > 0.0)
+            .fold((0.0, 0.0), |(deviation, variance), tally| {
+                let rolls = tally.rolls() as f64;
+                (deviation + (tally.average() - tally.expected()) * rolls, variance + tally.variance() * rolls)
+            });
+        if variance > 0.0 { deviation / variance.sqrt() } else { 0.0 }
+    }
+}
+
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Statistics {
+    pub tallies: Vec<Tally>,
+    // Oldest first
+    pub sessions: Vec<Session>,
+}
+
+fn tally_for(tallies: &mut Vec<Tally>, kind: DieKind) -> &mut Tally {
+    match tallies.iter().position(|tally| tally.kind == kind) {
+        Some(i) => &mut tallies[i],
+        None => {
+            tallies.push(Tally::new(kind));
+            tallies.last_mut().unwrap()
+        }
+    }
+}
+
+thread_local! {
+    static STATISTICS: RefCell<Statistics> = RefCell::new(load());
+    // When this run of the app rolled its first die
+    static SESSION_START: Cell<Option<i64>> = const { Cell::new(None) };
+    // Set while counts are waiting to be written out
+    static SAVE_PENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn statistics() -> Statistics {
+    STATISTICS.with(|stats| stats.borrow().clone())
+}
+
+// Whether `session` is the one this run of the app is counting
+pub fn is_current(session: &Session) -> bool {
+    SESSION_START.with(|start| start.get() == Some(session.started))
+}
+
+// Counts the faces dice settled on, as (kind, face)
+pub fn record(faces: &[(DieKind, u32)]) {
+    if faces.is_empty() {
+        return;
+    }
+    let started = SESSION_START.with(|start| match start.get() {
+        Some(started) => started,
+        None => {
+            let now = glib::DateTime::now_local().map(|now| now.to_unix()).unwrap_or(0);
+            start.set(Some(now));
+            now
+        }
+    });
+
+    STATISTICS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        if stats.sessions.last().is_none_or(|session| session.started != started) {
+            stats.sessions.push(Session { started, tallies: Vec::new() });
+            let excess = stats.sessions.len().saturating_sub(MAX_SESSIONS);
+            stats.sessions.drain(..excess);
+        }
+        for &(kind, face) in faces {
+            tally_for(&mut stats.tallies, kind).record(face);
+            if let Some(session) = stats.sessions.last_mut() {
+                tally_for(&mut session.tallies, kind).record(face);
+            }
+        }
+    });
+    if !SAVE_PENDING.with(|pending| pending.replace(true)) {
+        glib::timeout_add_seconds_local_once(SAVE_DELAY, flush);
+    }
+}
+
+// Writes out any counts that haven't been saved yet
+pub fn flush() {
+    if SAVE_PENDING.with(|pending| pending.replace(false)) {
+        STATISTICS.with(|stats| save(&stats.borrow()));
+    }
+}
+
+pub fn reset() {
+    STATISTICS.with(|stats| {
+        let mut stats = stats.borrow_mut();
+        *stats = Statistics::default();
+        save(&stats);
+    });
+    SAVE_PENDING.with(|pending| pending.set(false));
+    SESSION_START.with(|start| start.set(None));
+}
+
+// Every count as CSV, lifetime rows first and then one set per session
+pub fn to_csv(stats: &Statistics) -> String {
+    let mut csv = String::from("session,die,face,count\n");
+    let sessions = std::iter::once(("lifetime".to_string(), &stats.tallies))
+        .chain(stats.sessions.iter().map(|session| {
+            let started = glib::DateTime::from_unix_local(session.started)
+                .and_then(|date| date.format_iso8601())
+                .map(String::from)
+                .unwrap_or_else(|_| session.started.to_string());
+            (started, &session.tallies)
+        }));
+    for (session, tallies) in sessions {
+        for tally in tallies {
+            for (i, count) in tally.counts.iter().enumerate() {
+                csv.push_str(&format!("{},{},{},{}\n", session, tally.kind.name().replace(',', " "), i + 1, count));
+            }
+        }
+    }
+    csv
+}
+
+fn path() -> PathBuf {
+    let mut path = glib::user_data_dir();
+    path.push("dice");
+    path.push("statistics.json");
+    path
+}
+
+fn save(stats: &Statistics) {
+    let path = path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    if let Ok(json) = serde_json::to_string(stats) {
+        fs::write(&path, json).ok();
+    }
+}
+
+fn load() -> Statistics {
+    fs::read_to_string(path())
+        .ok()
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default()
+}
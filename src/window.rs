@@ -20,14 +20,34 @@
 
 use gtk::prelude::*;
 use adw::subclass::prelude::*;
-use gtk::{gdk, gio, glib};
+use gtk::{gdk, gio, glib, pango};
 
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::dice_area::DiceArea;
+use crate::ability_scores;
+use crate::analysis;
+use crate::dashboard;
+use crate::custom_dice;
+use crate::dice_area::{DiceArea, GroupInfo, SettledDie, GROUP_COLORS};
+use crate::die::{Advantage, Crit, CritRule, Keep, Pool, Reroll, Target};
+use crate::notation::{self, ParseError, Roll};
+use crate::outcomes;
+use crate::roll_history::{format_modifier, format_successes, RollSnapshot};
 use crate::sidebar::Sidebar;
 
+// Entries of the keep/drop dropdown, in `keep_mode` index order
+const KEEP_MODES: [&str; 5] = ["Keep All", "Keep Highest", "Keep Lowest", "Drop Highest", "Drop Lowest"];
+const REROLL_MODES: [&str; 3] = ["No Rerolls", "Reroll Once ≤", "Reroll Until >"];
+const TARGET_MODES: [&str; 3] = ["No Target", "Meet or Beat", "Roll Under"];
+const NO_OUTCOME: &str = "No Outcome";
+
+// Window actions bound to bare keys, which must not fire while typing notation
+const SINGLE_KEY_ACTIONS: [&str; 13] = [
+    "roll-d4", "roll-d6", "roll-d8", "roll-d10", "roll-d12", "roll-d20", "roll-d100", "roll-df", "reroll", "clear",
+    "modifier-increase", "modifier-decrease", "select-dice",
+];
+
 mod imp {
     use super::*;
 
@@ -48,6 +68,10 @@ mod imp {
         #[template_child]
         pub dice_labels: TemplateChild<gtk::Fixed>,
         #[template_child]
+        pub notation_entry: TemplateChild<gtk::Entry>,
+        #[template_child]
+        pub notation_error: TemplateChild<gtk::Label>,
+        #[template_child]
         pub four_side: TemplateChild<gtk::Button>,
         #[template_child]
         pub six_side: TemplateChild<gtk::Button>,
@@ -58,11 +82,69 @@ mod imp {
         #[template_child]
         pub twenty_side: TemplateChild<gtk::Button>,
         #[template_child]
+        pub hundred_side: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub fudge_side: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub custom_sides: TemplateChild<gtk::SpinButton>,
+        #[template_child]
+        pub custom_popover: TemplateChild<gtk::Popover>,
+        #[template_child]
+        pub defined_dice: TemplateChild<gtk::Box>,
+        #[template_child]
         pub total_label: TemplateChild<gtk::Label>,
         #[template_child]
+        pub results_button: TemplateChild<gtk::MenuButton>,
+        #[template_child]
+        pub results_popover: TemplateChild<gtk::Popover>,
+        #[template_child]
+        pub results_grid: TemplateChild<gtk::Grid>,
+        #[template_child]
+        pub repeat_count: TemplateChild<gtk::SpinButton>,
+        #[template_child]
+        pub modifier_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub keep_button: TemplateChild<gtk::MenuButton>,
+        #[template_child]
+        pub keep_mode: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub keep_count: TemplateChild<gtk::SpinButton>,
+        #[template_child]
+        pub reroll_rule_button: TemplateChild<gtk::MenuButton>,
+        #[template_child]
+        pub reroll_rule_mode: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub reroll_rule_threshold: TemplateChild<gtk::SpinButton>,
+        #[template_child]
+        pub pool_button: TemplateChild<gtk::MenuButton>,
+        #[template_child]
+        pub pool_enabled: TemplateChild<gtk::CheckButton>,
+        #[template_child]
+        pub pool_target: TemplateChild<gtk::SpinButton>,
+        #[template_child]
+        pub pool_ones: TemplateChild<gtk::CheckButton>,
+        #[template_child]
+        pub pool_tens: TemplateChild<gtk::CheckButton>,
+        #[template_child]
+        pub outcome_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub outcome_button: TemplateChild<gtk::MenuButton>,
+        #[template_child]
+        pub outcome_popover: TemplateChild<gtk::Popover>,
+        #[template_child]
+        pub outcome_mode: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub target_button: TemplateChild<gtk::MenuButton>,
+        #[template_child]
+        pub target_mode: TemplateChild<gtk::DropDown>,
+        #[template_child]
+        pub target_value: TemplateChild<gtk::SpinButton>,
+        #[template_child]
         pub reroll_button: TemplateChild<gtk::Button>,
         #[template_child]
         pub clear_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub crit_button: TemplateChild<gtk::MenuButton>,
 
         pub sidebar: RefCell<Option<Rc<RefCell<Sidebar>>>>,
     }
@@ -97,8 +179,8 @@ mod imp {
 
             // Create sidebar
             let dice_area_for_restore = self.dice_area.clone();
-            let sidebar = Sidebar::new(move |dice| {
-                dice_area_for_restore.restore_roll(dice);
+            let sidebar = Sidebar::new(move |entry| {
+                dice_area_for_restore.restore_roll(entry);
             });
 
             self.split_view.set_sidebar(Some(sidebar.borrow().widget()));
@@ -197,10 +279,45 @@ mod imp {
             });
             self.obj().add_action(&action);
 
+            let dice_area = self.dice_area.clone();
+            let sidebar_ref = self.sidebar.borrow().clone();
+            let settings_clone = settings.clone();
+            let action = gio::SimpleAction::new("roll-d100", None);
+            action.connect_activate(move |_, _| {
+                if settings_clone.boolean("record-all-rolls") {
+                    if let Some(ref sidebar_rc) = sidebar_ref {
+                        let snapshot = dice_area.dice_snapshot();
+                        sidebar_rc.borrow().add_recent(snapshot, sidebar_rc);
+                    }
+                }
+                dice_area.add_hundred();
+            });
+            self.obj().add_action(&action);
+
+            let dice_area = self.dice_area.clone();
+            let sidebar_ref = self.sidebar.borrow().clone();
+            let settings_clone = settings.clone();
+            let action = gio::SimpleAction::new("roll-df", None);
+            action.connect_activate(move |_, _| {
+                if settings_clone.boolean("record-all-rolls") {
+                    if let Some(ref sidebar_rc) = sidebar_ref {
+                        let snapshot = dice_area.dice_snapshot();
+                        sidebar_rc.borrow().add_recent(snapshot, sidebar_rc);
+                    }
+                }
+                dice_area.add_fudge();
+            });
+            self.obj().add_action(&action);
+
             let dice_area = self.dice_area.clone();
             let sidebar_ref = self.sidebar.borrow().clone();
             let action = gio::SimpleAction::new("reroll", None);
             action.connect_activate(move |_, _| {
+                // Selected dice are rerolled in place as part of the current roll
+                if dice_area.has_selection() {
+                    dice_area.reroll_selected();
+                    return;
+                }
                 if let Some(ref sidebar_rc) = sidebar_ref {
                     let snapshot = dice_area.dice_snapshot();
                     sidebar_rc.borrow().add_recent(snapshot, sidebar_rc);
@@ -221,6 +338,20 @@ mod imp {
             });
             self.obj().add_action(&action);
 
+            let dice_area = self.dice_area.clone();
+            let action = gio::SimpleAction::new("modifier-increase", None);
+            action.connect_activate(move |_, _| {
+                dice_area.set_modifier(dice_area.modifier() + 1);
+            });
+            self.obj().add_action(&action);
+
+            let dice_area = self.dice_area.clone();
+            let action = gio::SimpleAction::new("modifier-decrease", None);
+            action.connect_activate(move |_, _| {
+                dice_area.set_modifier(dice_area.modifier() - 1);
+            });
+            self.obj().add_action(&action);
+
             // Toggle sidebar action
             let split_view = self.split_view.clone();
             let action = gio::SimpleAction::new("toggle-sidebar", None);
@@ -229,11 +360,184 @@ mod imp {
             });
             self.obj().add_action(&action);
 
+            // "normal", "advantage" or "disadvantage"; activating the current mode turns it off
+            let dice_area = self.dice_area.clone();
+            let action = gio::SimpleAction::new_stateful("d20-mode", Some(glib::VariantTy::STRING), &"normal".to_variant());
+            action.connect_activate(move |action, target| {
+                let Some(mode) = target.and_then(|t| t.get::<String>()) else { return };
+                let current = action.state().and_then(|s| s.get::<String>());
+                let mode = if current.as_deref() == Some(mode.as_str()) { "normal".to_string() } else { mode };
+                dice_area.set_advantage(match mode.as_str() {
+                    "advantage" => Some(Advantage::Advantage),
+                    "disadvantage" => Some(Advantage::Disadvantage),
+                    _ => None,
+                });
+                action.set_state(&mode.to_variant());
+            });
+            self.obj().add_action(&action);
+
+            // "double-dice", "max-plus-roll" or "double-total"
+            let dice_area = self.dice_area.clone();
+            let action = gio::SimpleAction::new("crit-damage", Some(glib::VariantTy::STRING));
+            action.connect_activate(move |_, target| {
+                let Some(rule) = target.and_then(|t| t.get::<String>()).and_then(|name| CritRule::from_name(&name)) else { return };
+                dice_area.apply_crit(rule);
+            });
+            self.obj().add_action(&action);
+
+            let dice_area = self.dice_area.clone();
+            let action = gio::SimpleAction::new_stateful("select-dice", None, &false.to_variant());
+            action.connect_activate(move |action, _| {
+                let selecting = !action.state().and_then(|s| s.get::<bool>()).unwrap_or(false);
+                dice_area.set_selecting(selecting);
+                action.set_state(&selecting.to_variant());
+            });
+            self.obj().add_action(&action);
+
+            let notation_entry = self.notation_entry.clone();
+            let action = gio::SimpleAction::new("focus-notation", None);
+            action.connect_activate(move |_, _| {
+                notation_entry.grab_focus();
+            });
+            self.obj().add_action(&action);
+
+            let window = self.obj().downgrade();
+            let action = gio::SimpleAction::new("ability-scores", None);
+            action.connect_activate(move |_, _| {
+                if let Some(window) = window.upgrade() {
+                    ability_scores::show(&window);
+                }
+            });
+            self.obj().add_action(&action);
+
+            let window = self.obj().downgrade();
+            let action = gio::SimpleAction::new("odds", None);
+            action.connect_activate(move |_, _| {
+                if let Some(window) = window.upgrade() {
+                    analysis::show(&window);
+                }
+            });
+            self.obj().add_action(&action);
+
+            let window = self.obj().downgrade();
+            let action = gio::SimpleAction::new("statistics", None);
+            action.connect_activate(move |_, _| {
+                if let Some(window) = window.upgrade() {
+                    dashboard::show(&window);
+                }
+            });
+            self.obj().add_action(&action);
+
+            self.obj().suspend_single_key_actions_on_focus(&*self.notation_entry);
+            self.obj().suspend_single_key_actions_on_focus(&*self.repeat_count);
+            self.obj().suspend_single_key_actions_on_focus(&*self.custom_sides);
+            self.obj().suspend_single_key_actions_on_focus(&*self.keep_count);
+            self.obj().suspend_single_key_actions_on_focus(&*self.reroll_rule_threshold);
+            self.obj().suspend_single_key_actions_on_focus(&*self.pool_target);
+            self.obj().suspend_single_key_actions_on_focus(&*self.target_value);
+
+            let window = self.obj().downgrade();
+            self.results_popover.connect_show(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.refresh_results_table();
+                }
+            });
+
+            // Custom dice can change in Preferences, so list them afresh each time
+            let window = self.obj().downgrade();
+            self.custom_popover.connect_show(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.refresh_defined_dice();
+                }
+            });
+
+            self.keep_mode.set_model(Some(&gtk::StringList::new(&KEEP_MODES)));
+            let window = self.obj().downgrade();
+            self.keep_mode.connect_selected_notify(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.apply_keep_selection();
+                }
+            });
+            let window = self.obj().downgrade();
+            self.keep_count.connect_value_changed(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.apply_keep_selection();
+                }
+            });
+
+            self.reroll_rule_mode.set_model(Some(&gtk::StringList::new(&REROLL_MODES)));
+            let window = self.obj().downgrade();
+            self.reroll_rule_mode.connect_selected_notify(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.apply_reroll_rule_selection();
+                }
+            });
+            let window = self.obj().downgrade();
+            self.reroll_rule_threshold.connect_value_changed(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.apply_reroll_rule_selection();
+                }
+            });
+
+            for check in [&*self.pool_enabled, &*self.pool_ones, &*self.pool_tens] {
+                let window = self.obj().downgrade();
+                check.connect_toggled(move |_| {
+                    if let Some(window) = window.upgrade() {
+                        window.apply_pool_selection();
+                    }
+                });
+            }
+            let window = self.obj().downgrade();
+            self.pool_target.connect_value_changed(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.apply_pool_selection();
+                }
+            });
+
+            // User-defined interpreters can change in Preferences, so list them afresh each time
+            let window = self.obj().downgrade();
+            self.outcome_popover.connect_show(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.refresh_outcome_modes();
+                }
+            });
+            let window = self.obj().downgrade();
+            self.outcome_mode.connect_selected_notify(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.apply_outcome_selection();
+                }
+            });
+
+            self.target_mode.set_model(Some(&gtk::StringList::new(&TARGET_MODES)));
+            let window = self.obj().downgrade();
+            self.target_mode.connect_selected_notify(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.apply_target_selection();
+                }
+            });
+            let window = self.obj().downgrade();
+            self.target_value.connect_value_changed(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.apply_target_selection();
+                }
+            });
+
+            let window = self.obj().downgrade();
+            self.notation_entry.connect_changed(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.clear_notation_error();
+                }
+            });
+
             let dice_area = self.dice_area.clone();
             let dice_labels = self.dice_labels.clone();
             let total_label = self.total_label.clone();
+            let outcome_label = self.outcome_label.clone();
+            let results_button = self.results_button.clone();
+            let modifier_label = self.modifier_label.clone();
             let reroll_button = self.reroll_button.clone();
             let clear_button = self.clear_button.clone();
+            let crit_button = self.crit_button.clone();
             self.obj().add_tick_callback(move |_widget, _clock| {
                 // Remove old labels
                 let mut child = dice_labels.first_child();
@@ -244,36 +548,142 @@ mod imp {
 
                 // Add labels for settled dice
                 let infos = dice_area.settled_dice_info();
-                for (wx, wy, val) in &infos {
-                    let label = gtk::Label::new(Some(&val.to_string()));
-                    label.add_css_class("die-number");
-                    label.set_can_target(false);
-                    let (_, nat_w, _, _) = label.measure(gtk::Orientation::Horizontal, -1);
-                    let (_, nat_h, _, _) = label.measure(gtk::Orientation::Vertical, -1);
-                    dice_labels.put(&label, (*wx - nat_w as f32 / 2.0) as f64, (*wy - nat_h as f32 / 2.0) as f64);
+                for die in &infos {
+                    for (wx, wy, text) in &die.faces {
+                        let label = gtk::Label::new(Some(text));
+                        label.add_css_class("die-number");
+                        if die.selected {
+                            label.add_css_class("selected");
+                        }
+                        if !die.kept {
+                            label.add_css_class("dropped");
+                        } else if let Some(crit) = die.crit {
+                            label.add_css_class(match crit {
+                                Crit::Success => "crit-success",
+                                Crit::Failure => "crit-failure",
+                            });
+                        } else if die.crit_extra {
+                            label.add_css_class("crit-extra");
+                        } else if die.successes > 0 {
+                            label.add_css_class("hit");
+                        } else if die.successes < 0 {
+                            label.add_css_class("botch");
+                        } else if die.advantage.is_some() {
+                            label.add_css_class("kept");
+                        }
+                        label.set_can_target(false);
+                        let (_, nat_w, _, _) = label.measure(gtk::Orientation::Horizontal, -1);
+                        let (_, nat_h, _, _) = label.measure(gtk::Orientation::Vertical, -1);
+                        dice_labels.put(&label, (*wx - nat_w as f32 / 2.0) as f64, (*wy - nat_h as f32 / 2.0) as f64);
+
+                        // Say which die of an advantage pair counted
+                        if let (true, Some(advantage)) = (die.kept, die.advantage) {
+                            let caption = gtk::Label::new(Some(&advantage.abbreviation().to_uppercase()));
+                            caption.add_css_class("die-caption");
+                            caption.set_can_target(false);
+                            let (_, cap_w, _, _) = caption.measure(gtk::Orientation::Horizontal, -1);
+                            dice_labels.put(&caption, (*wx - cap_w as f32 / 2.0) as f64, (*wy + nat_h as f32 / 2.0) as f64);
+                        }
+                    }
+                }
+
+                // Each named group's subtotal, captioned above its cluster of dice
+                let groups = dice_area.groups();
+                let modifier = dice_area.modifier();
+                // Doubling the total on a crit doubles every group's share of it too
+                let factor = if dice_area.crit_rule() == Some(CritRule::DoubleTotal) { 2 } else { 1 };
+                let subtotal = |group: u32| group_subtotal(&infos, &groups, modifier, group) * factor;
+                if !infos.is_empty() {
+                    for (x, y, group) in dice_area.group_captions() {
+                        let Some(info) = groups.get(group as usize - 1) else { continue };
+                        let caption = gtk::Label::new(Some(&format!("{}: {}", info.name, subtotal(group))));
+                        caption.add_css_class("group-name");
+                        caption.add_css_class(&format!("group-{}", (group as usize - 1) % GROUP_COLORS.len()));
+                        caption.set_can_target(false);
+                        let (_, cap_w, _, _) = caption.measure(gtk::Orientation::Horizontal, -1);
+                        let (_, cap_h, _, _) = caption.measure(gtk::Orientation::Vertical, -1);
+                        dice_labels.put(&caption, (x - cap_w as f32 / 2.0) as f64, (y - cap_h as f32) as f64);
+                    }
                 }
 
                 // Update total label
                 let has_dice = dice_area.has_dice();
+                modifier_label.set_text(&format_modifier(modifier));
                 if !infos.is_empty() {
-                    let sum: u32 = infos.iter().map(|(_, _, v)| v).sum();
-                    total_label.set_text(&format!("{}", sum));
+                    let kept = infos.iter().filter(|die| die.kept);
+                    let (text, total) = if dice_area.pool().is_some() {
+                        let successes: i32 = kept.map(|die| die.successes).sum::<i32>() + modifier;
+                        (format_successes(successes), successes)
+                    } else if !groups.is_empty() {
+                        // Dice added outside the notation's groups are listed as "Other"
+                        let ungrouped = infos.iter().any(|die| die.group == 0);
+                        let other = ungrouped.then(|| format!("Other {}", subtotal(0)));
+                        let parts: Vec<String> = other.into_iter()
+                            .chain(groups.iter().enumerate().map(|(i, info)| format!("{} {}", info.name, subtotal(i as u32 + 1))))
+                            .collect();
+                        let total = (1..=groups.len() as u32).map(&subtotal).sum::<i32>()
+                            + if ungrouped { subtotal(0) } else { 0 };
+                        (parts.join(" · "), total)
+                    } else {
+                        let kept: Vec<_> = kept.collect();
+                        let sum: i32 = (kept.iter().map(|die| die.value).sum::<i32>() + modifier) * factor;
+                        let tally = custom_dice::format_tally(kept.iter().flat_map(|die| die.symbols.iter().map(String::as_str)));
+                        let symbolic = modifier == 0 && kept.iter().all(|die| custom_dice::is_symbolic(die.kind));
+                        (custom_dice::format_total(sum, tally, symbolic), sum)
+                    };
+                    let text = match dice_area.crit_rule() {
+                        Some(rule) => format!("{} · Crit ({})", text, rule.label()),
+                        None => text,
+                    };
+
+                    // Against a target the pill says whether the roll passed and by how much
+                    total_label.remove_css_class("success");
+                    total_label.remove_css_class("error");
+                    match dice_area.target() {
+                        Some(target) => {
+                            let passed = target.passes(total);
+                            total_label.set_text(&format!(
+                                "{} {} ({})",
+                                text,
+                                if passed { "✔" } else { "✘" },
+                                format_modifier(target.margin(total)),
+                            ));
+                            total_label.add_css_class(if passed { "success" } else { "error" });
+                        }
+                        None => total_label.set_text(&text),
+                    }
                     total_label.set_visible(true);
+
+                    let values: Vec<i32> = infos.iter().filter(|die| die.kept).map(|die| die.value).collect();
+                    let outcome = dice_area.interpreter().and_then(|interpreter| interpreter.read(&values, total).map(outcomes::markup));
+                    outcome_label.set_markup(outcome.as_deref().unwrap_or_default());
+                    outcome_label.set_visible(outcome.is_some());
                 } else if !has_dice {
                     total_label.set_visible(false);
+                    outcome_label.set_visible(false);
                 }
 
+                results_button.set_visible(has_dice && dice_area.repeats() > 1);
+
                 // Update button sensitivity
                 reroll_button.set_sensitive(has_dice);
                 clear_button.set_sensitive(has_dice);
+                crit_button.set_sensitive(has_dice && dice_area.crit_rule().is_none());
 
                 glib::ControlFlow::Continue
             });
 
+            // Group captions match the tint of their dice
+            let group_css: String = GROUP_COLORS.iter()
+                .enumerate()
+                .map(|(i, color)| format!(" .group-name.group-{} {{ color: {}; }}", i, color))
+                .collect();
             let css = gtk::CssProvider::new();
-            css.load_from_string(
-                ".die-number { font-size: 24px; font-weight: bold; color: white; text-shadow: 0 1px 3px rgba(0,0,0,0.8); } .total-pill { font-weight: bold; padding: 4px 12px; }",
-            );
+            css.load_from_string(&format!(
+                "{}{}",
+                ".die-number { font-size: 24px; font-weight: bold; color: white; text-shadow: 0 1px 3px rgba(0,0,0,0.8); } .die-number.dropped { opacity: 0.5; text-decoration: line-through; } .die-number.kept { color: #f6d32d; } .die-number.hit { color: #8ff0a4; } .die-number.botch { color: #f66151; } .die-number.crit-success { color: #f6d32d; font-size: 30px; } .die-number.crit-failure { color: #f66151; font-size: 30px; } .die-number.crit-extra { color: #dc8add; font-style: italic; } .die-caption { font-size: 11px; font-weight: bold; color: white; text-shadow: 0 1px 3px rgba(0,0,0,0.8); } .die-number.selected { background-color: alpha(@accent_bg_color, 0.8); border-radius: 999px; padding: 0 8px; } .total-pill { font-weight: bold; padding: 4px 12px; } .group-name { font-size: 13px; font-weight: bold; text-shadow: 0 1px 3px rgba(0,0,0,0.8); }",
+                group_css,
+            ));
             self.total_label.add_css_class("total-pill");
             self.total_label.add_css_class("dim-label");
             gtk::style_context_add_provider_for_display(
@@ -303,6 +713,27 @@ impl DiceWindow {
             .build()
     }
 
+    pub fn dice_area(&self) -> DiceArea {
+        self.imp().dice_area.clone()
+    }
+
+    // Adds an entry to Recents
+    pub fn record(&self, snapshot: RollSnapshot) {
+        if let Some(ref sidebar_rc) = *self.imp().sidebar.borrow() {
+            sidebar_rc.borrow().add_recent(snapshot, sidebar_rc);
+        }
+    }
+
+    // Records what is in the tray, then rolls the notation in its place, as
+    // long as its dice fit
+    pub fn roll_notation(&self, roll: &Roll, repeats: u32) -> Result<(), String> {
+        let imp = self.imp();
+        let snapshot = imp.dice_area.dice_snapshot();
+        imp.dice_area.roll_expression(roll, repeats)?;
+        self.record(snapshot);
+        Ok(())
+    }
+
     fn snapshot_if_recording(&self) {
         let settings = gio::Settings::new("org.lesslie.dice");
         if settings.boolean("record-all-rolls") {
@@ -314,6 +745,261 @@ impl DiceWindow {
         }
     }
 
+    fn set_single_key_actions_enabled(&self, enabled: bool) {
+        for name in SINGLE_KEY_ACTIONS {
+            if let Some(action) = self.lookup_action(name).and_downcast::<gio::SimpleAction>() {
+                action.set_enabled(enabled);
+            }
+        }
+    }
+
+    // Bare-key shortcuts would otherwise swallow digits typed into `widget`
+    fn suspend_single_key_actions_on_focus(&self, widget: &impl IsA<gtk::Widget>) {
+        let focus = gtk::EventControllerFocus::new();
+        let window = self.downgrade();
+        focus.connect_enter(move |_| {
+            if let Some(window) = window.upgrade() {
+                window.set_single_key_actions_enabled(false);
+            }
+        });
+        let window = self.downgrade();
+        focus.connect_leave(move |_| {
+            if let Some(window) = window.upgrade() {
+                window.set_single_key_actions_enabled(true);
+            }
+        });
+        widget.add_controller(focus);
+    }
+
+    // One row per repetition, with a column for each group of the notation and their total
+    fn refresh_results_table(&self) {
+        let imp = self.imp();
+        let grid = &imp.results_grid;
+        while let Some(child) = grid.first_child() {
+            grid.remove(&child);
+        }
+
+        let infos = imp.dice_area.settled_dice_info();
+        let groups = imp.dice_area.groups();
+        let modifier = imp.dice_area.modifier();
+        let repeats = imp.dice_area.repeats() as usize;
+        if !groups.len().is_multiple_of(repeats) {
+            return;
+        }
+        let per_repeat = groups.len() / repeats;
+
+        let cell = |text: &str, class: Option<&str>| {
+            let label = gtk::Label::builder().label(text).xalign(1.0).build();
+            if let Some(class) = class {
+                label.add_css_class(class);
+            }
+            label
+        };
+
+        // Columns are named after the groups of the first repetition
+        let mut headers: Vec<String> = groups[..per_repeat].iter()
+            .map(|info| info.name.strip_suffix(" #1").unwrap_or(&info.name).to_string())
+            .filter(|_| per_repeat > 1)
+            .collect();
+        headers.push("Total".to_string());
+        for (col, header) in headers.iter().enumerate() {
+            grid.attach(&cell(header, Some("heading")), col as i32 + 1, 0, 1, 1);
+        }
+
+        for rep in 0..repeats {
+            let row = rep as i32 + 1;
+            grid.attach(&cell(&format!("#{}", rep + 1), Some("dim-label")), 0, row, 1, 1);
+            let subtotals: Vec<i32> = (0..per_repeat)
+                .map(|g| group_subtotal(&infos, &groups, modifier, (rep * per_repeat + g) as u32 + 1))
+                .collect();
+            if per_repeat > 1 {
+                for (col, subtotal) in subtotals.iter().enumerate() {
+                    grid.attach(&cell(&subtotal.to_string(), None), col as i32 + 1, row, 1, 1);
+                }
+            }
+            let total: i32 = subtotals.iter().sum();
+            grid.attach(&cell(&total.to_string(), Some("heading")), headers.len() as i32, row, 1, 1);
+        }
+    }
+
+    fn refresh_defined_dice(&self) {
+        let imp = self.imp();
+        while let Some(child) = imp.defined_dice.first_child() {
+            imp.defined_dice.remove(&child);
+        }
+
+        let definitions = custom_dice::definitions();
+        imp.defined_dice.set_visible(!definitions.is_empty());
+        for def in definitions {
+            let button = gtk::Button::with_label(&def.name);
+            let id = def.id();
+            let window = self.downgrade();
+            button.connect_clicked(move |_| {
+                if let Some(window) = window.upgrade() {
+                    window.snapshot_if_recording();
+                    window.imp().dice_area.add_defined(id);
+                }
+            });
+            imp.defined_dice.append(&button);
+        }
+    }
+
+    fn apply_keep_selection(&self) {
+        let imp = self.imp();
+        let mode = imp.keep_mode.selected() as usize;
+        let n = imp.keep_count.value_as_int().max(1) as u32;
+        let keep = match mode {
+            1 => Some(Keep::Highest(n)),
+            2 => Some(Keep::Lowest(n)),
+            3 => Some(Keep::DropHighest(n)),
+            4 => Some(Keep::DropLowest(n)),
+            _ => None,
+        };
+
+        imp.keep_count.set_sensitive(keep.is_some());
+        let label = match keep {
+            Some(_) => format!("{} {}", KEEP_MODES[mode], n),
+            None => KEEP_MODES[0].to_string(),
+        };
+        imp.keep_button.set_label(&label);
+        imp.dice_area.set_keep(keep);
+    }
+
+    fn apply_reroll_rule_selection(&self) {
+        let imp = self.imp();
+        let mode = imp.reroll_rule_mode.selected() as usize;
+        let n = imp.reroll_rule_threshold.value_as_int().max(1) as u32;
+        let rule = match mode {
+            1 => Some(Reroll::Once(n)),
+            2 => Some(Reroll::Until(n)),
+            _ => None,
+        };
+
+        imp.reroll_rule_threshold.set_sensitive(rule.is_some());
+        let label = match rule {
+            Some(_) => format!("{} {}", REROLL_MODES[mode], n),
+            None => REROLL_MODES[0].to_string(),
+        };
+        imp.reroll_rule_button.set_label(&label);
+        imp.dice_area.set_reroll_rule(rule);
+    }
+
+    fn apply_pool_selection(&self) {
+        let imp = self.imp();
+        let enabled = imp.pool_enabled.is_active();
+        let pool = enabled.then(|| Pool {
+            target: imp.pool_target.value_as_int().max(1) as u32,
+            ones_subtract: imp.pool_ones.is_active(),
+            tens_double: imp.pool_tens.is_active(),
+        });
+
+        imp.pool_target.set_sensitive(enabled);
+        imp.pool_ones.set_sensitive(enabled);
+        imp.pool_tens.set_sensitive(enabled);
+        let label = match pool {
+            Some(pool) => format!("Successes ≥ {}", pool.target),
+            None => "Sum".to_string(),
+        };
+        imp.pool_button.set_label(&label);
+        imp.dice_area.set_pool(pool);
+    }
+
+    fn refresh_outcome_modes(&self) {
+        let imp = self.imp();
+        let current = imp.dice_area.interpreter().map(|interpreter| interpreter.name);
+        let mut names = vec![NO_OUTCOME.to_string()];
+        names.extend(outcomes::all().into_iter().map(|interpreter| interpreter.name));
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        imp.outcome_mode.set_model(Some(&gtk::StringList::new(&names)));
+        let selected = current.and_then(|name| names.iter().position(|n| *n == name)).unwrap_or(0);
+        imp.outcome_mode.set_selected(selected as u32);
+    }
+
+    fn apply_outcome_selection(&self) {
+        let imp = self.imp();
+        let Some(name) = imp.outcome_mode.selected_item()
+            .and_downcast::<gtk::StringObject>()
+            .map(|item| item.string())
+        else { return };
+
+        let interpreter = outcomes::all().into_iter().find(|interpreter| interpreter.name == name.as_str());
+        imp.outcome_button.set_label(interpreter.as_ref().map_or(NO_OUTCOME, |interpreter| interpreter.name.as_str()));
+        imp.dice_area.set_interpreter(interpreter);
+    }
+
+    fn apply_target_selection(&self) {
+        let imp = self.imp();
+        let mode = imp.target_mode.selected() as usize;
+        let target = (mode > 0).then(|| Target {
+            value: imp.target_value.value_as_int(),
+            roll_under: mode == 2,
+        });
+
+        imp.target_value.set_sensitive(target.is_some());
+        let label = match target {
+            Some(target) => target.to_string(),
+            None => TARGET_MODES[0].to_string(),
+        };
+        imp.target_button.set_label(&label);
+        imp.dice_area.set_target(target);
+    }
+
+    fn show_notation_error(&self, text: &str, err: &ParseError) {
+        let imp = self.imp();
+
+        // Underline the offending token, or the last character if input ended early
+        let attrs = pango::AttrList::new();
+        let column = err.column.min(text.chars().count().saturating_sub(1));
+        if let Some((start, first)) = text.char_indices().nth(column) {
+            let end = if first.is_ascii_alphanumeric() {
+                text[start..]
+                    .find(|c: char| !c.is_ascii_alphanumeric())
+                    .map(|len| start + len)
+                    .unwrap_or(text.len())
+            } else {
+                start + first.len_utf8()
+            };
+
+            let mut underline = pango::AttrInt::new_underline(pango::Underline::Error);
+            underline.set_start_index(start as u32);
+            underline.set_end_index(end as u32);
+            attrs.insert(underline);
+
+            let mut color = pango::AttrColor::new_foreground(0xe0e0, 0x1b1b, 0x2424);
+            color.set_start_index(start as u32);
+            color.set_end_index(end as u32);
+            attrs.insert(color);
+        }
+
+        imp.notation_entry.set_attributes(&attrs);
+        imp.notation_entry.add_css_class("error");
+        imp.notation_entry.set_position(err.column as i32);
+        imp.notation_error.set_text(&err.to_string());
+        imp.notation_error.set_visible(true);
+    }
+
+    fn clear_notation_error(&self) {
+        let imp = self.imp();
+        imp.notation_entry.set_attributes(&pango::AttrList::new());
+        imp.notation_entry.remove_css_class("error");
+        imp.notation_error.set_visible(false);
+    }
+
+    #[template_callback]
+    fn handle_notation_activate(&self) {
+        let imp = self.imp();
+        let text = imp.notation_entry.text();
+        match notation::parse(&text) {
+            Ok(roll) => {
+                self.clear_notation_error();
+                if let Err(message) = self.roll_notation(&roll, imp.repeat_count.value_as_int() as u32) {
+                    self.show_notation_error(&text, &ParseError { column: 0, message });
+                }
+            }
+            Err(err) => self.show_notation_error(&text, &err),
+        }
+    }
+
     #[template_callback]
     fn handle_four_clicked(&self) {
         println!("Four clicked");
@@ -356,9 +1042,45 @@ impl DiceWindow {
         self.imp().dice_area.add_twenty();
     }
 
+    #[template_callback]
+    fn handle_hundred_clicked(&self) {
+        println!("Hundred clicked");
+        self.snapshot_if_recording();
+        self.imp().dice_area.add_hundred();
+    }
+
+    #[template_callback]
+    fn handle_fudge_clicked(&self) {
+        self.snapshot_if_recording();
+        self.imp().dice_area.add_fudge();
+    }
+
+    #[template_callback]
+    fn handle_custom_add_clicked(&self) {
+        let imp = self.imp();
+        self.snapshot_if_recording();
+        imp.dice_area.add_custom(imp.custom_sides.value_as_int() as u32);
+    }
+
+    #[template_callback]
+    fn handle_modifier_decrease_clicked(&self) {
+        let dice_area = &self.imp().dice_area;
+        dice_area.set_modifier(dice_area.modifier() - 1);
+    }
+
+    #[template_callback]
+    fn handle_modifier_increase_clicked(&self) {
+        let dice_area = &self.imp().dice_area;
+        dice_area.set_modifier(dice_area.modifier() + 1);
+    }
+
     #[template_callback]
     fn handle_reroll_clicked(&self) {
         let imp = &self.imp();
+        if imp.dice_area.has_selection() {
+            imp.dice_area.reroll_selected();
+            return;
+        }
         if let Some(ref sidebar_rc) = *imp.sidebar.borrow() {
             let snapshot = imp.dice_area.dice_snapshot();
             sidebar_rc.borrow().add_recent(snapshot, sidebar_rc);
@@ -376,3 +1098,9 @@ impl DiceWindow {
         imp.dice_area.clear();
     }
 }
+
+// What the dice of a group add up to with its modifier; group 0 holds the tray's own dice
+fn group_subtotal(infos: &[SettledDie], groups: &[GroupInfo], modifier: i32, group: u32) -> i32 {
+    let sum: i32 = infos.iter().filter(|die| die.kept && die.group == group).map(|die| die.value).sum();
+    sum + groups.get((group as usize).wrapping_sub(1)).map_or(modifier, |info| info.modifier)
+}